@@ -1,8 +1,8 @@
-use cc;
-
 fn main() {
+    // cc's own rerun-if lines stop cargo from watching the sources, so name them
+    println!("cargo:rerun-if-changed=tea32.c");
     cc::Build::new()
         .file("tea32.c")
         .opt_level_str("fast")
         .compile("tea32")
-}
\ No newline at end of file
+}
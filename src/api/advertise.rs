@@ -0,0 +1,126 @@
+use std::future::Future;
+use std::time::Duration;
+
+use futures::future::{select, Either};
+use futures::pin_mut;
+use serde::Serialize;
+use reqwest::{header::HeaderMap, Method};
+use zeroize::Zeroizing;
+
+use super::{ApiError, ApiResult, MasterServerApi, Reply, Server};
+
+/// What a hosted server publishes about itself, the same entry the DS3OS server posts.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct HostAdvertisement {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Description")]
+    pub description: String,
+    #[serde(rename = "Hostname")]
+    pub hostname: String,
+    #[serde(rename = "PrivateHostname")]
+    pub private_hostname: String,
+    #[serde(rename = "PublicKey")]
+    pub pubkey: String,
+    // Wiped from memory with every copy of the advertisement
+    #[serde(rename = "Password")]
+    pub passwd: Zeroizing<String>,
+    #[serde(rename = "PlayerCount")]
+    pub player_count: u32,
+    #[serde(rename = "ModsWhiteList")]
+    pub mods_white_list: String,
+    #[serde(rename = "ModsBlackList")]
+    pub mods_black_list: String,
+    #[serde(rename = "ModsRequiredList")]
+    pub mods_required_list: String,
+    #[serde(rename = "GameType", skip_serializing_if = "String::is_empty")]
+    pub game_type: String,
+}
+
+impl From<&Server> for HostAdvertisement {
+    fn from(s: &Server) -> HostAdvertisement {
+        HostAdvertisement {
+            name: s.name.clone(),
+            description: s.description.clone(),
+            hostname: s.hostname.clone(),
+            private_hostname: s.private_hostname.clone(),
+            pubkey: s.pubkey.clone(),
+            passwd: Zeroizing::new(s.passwd.clone()),
+            player_count: s.player_count,
+            mods_white_list: s.mods_white_list.clone(),
+            mods_black_list: s.mods_black_list.clone(),
+            mods_required_list: s.mods_required_list.clone(),
+            game_type: s.game_type.clone(),
+        }
+    }
+}
+
+impl MasterServerApi {
+    // The master keys advertisements by the sender's address, so there is nothing to identify in the path.
+    async fn advertise_request(&self, method: Method, body: Option<&HostAdvertisement>) -> ApiResult<()> {
+        let res = match self.request_versioned(method, "", HeaderMap::new(), body).await? {
+            Reply::Body(res, _) => res,
+            _ => return Err(ApiError::UnsupportedVersion),
+        };
+        if res.status == "success" {
+            Ok(())
+        }
+        else {
+            Err(ApiError::Server { status: res.status, message: res.message })
+        }
+    }
+
+    pub async fn register_server(&self, ad: &HostAdvertisement) -> ApiResult<()> {
+        self.advertise_request(Method::POST, Some(ad)).await
+    }
+
+    /// Refresh the entry so the master doesn't expire it, posting the whole advertisement again like the server does.
+    pub async fn heartbeat(&self, ad: &HostAdvertisement, player_count: u32) -> ApiResult<()> {
+        let ad = HostAdvertisement { player_count, ..ad.clone() };
+        self.advertise_request(Method::POST, Some(&ad)).await
+    }
+
+    pub async fn unregister_server(&self) -> ApiResult<()> {
+        self.advertise_request(Method::DELETE, None).await
+    }
+}
+
+/// Keeps a server listed on a master until told to stop.
+pub struct Advertiser {
+    api: MasterServerApi,
+    ad: HostAdvertisement,
+    interval: Duration,
+}
+
+impl Advertiser {
+    pub fn new(api: MasterServerApi, ad: HostAdvertisement, interval: Duration) -> Self {
+        Advertiser { api, ad, interval }
+    }
+
+    /// Register, heartbeat every interval with the current player count and unregister once `stop` completes.
+    /// A failed heartbeat goes to `on_heartbeat_error` and the loop carries on, the next one may succeed.
+    pub async fn run<C, E, F>(self, mut player_count: C, mut on_heartbeat_error: E, stop: F) -> ApiResult<()>
+    where
+        C: FnMut() -> u32,
+        E: FnMut(&ApiError),
+        F: Future<Output = ()>,
+    {
+        self.api.register_server(&self.ad).await?;
+
+        pin_mut!(stop);
+        loop {
+            let tick = tokio::time::sleep(self.interval);
+            pin_mut!(tick);
+            match select(stop.as_mut(), tick).await {
+                Either::Left(_) => break,
+                Either::Right(_) => {
+                    if let Err(e) = self.api.heartbeat(&self.ad, player_count()).await {
+                        on_heartbeat_error(&e);
+                    }
+                },
+            }
+        }
+
+        self.api.unregister_server().await
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use reqwest::{Url, header::*};
+
+use super::Server;
+
+// The last successful server list of one master, with what's needed to revalidate it.
+#[derive(Serialize, Deserialize)]
+pub struct ServerCache {
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+    // Seconds since the unix epoch
+    fetched_at: u64,
+    pub servers: Vec<Server>,
+}
+
+pub fn default_path(base_url: &Url) -> Option<PathBuf> {
+    let name: String = format!("{}_{}", base_url.host_str()?, base_url.port_or_known_default()?)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' })
+        .collect();
+    Some(dirs::cache_dir()?.join("ds3os-loader").join(format!("servers-{}.json", name)))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl ServerCache {
+    pub fn new(servers: Vec<Server>, headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|v: &HeaderValue| v.to_str().ok()).map(String::from);
+        ServerCache {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            fetched_at: now(),
+            servers,
+        }
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        serde_json::from_reader(BufReader::new(file)).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        serde_json::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    pub fn conditional_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(v) = self.etag.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.insert(IF_NONE_MATCH, v);
+        }
+        if let Some(v) = self.last_modified.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.insert(IF_MODIFIED_SINCE, v);
+        }
+        headers
+    }
+
+    // The master confirmed the cached list is still current
+    pub fn touched(&self) -> Self {
+        ServerCache {
+            etag: self.etag.clone(),
+            last_modified: self.last_modified.clone(),
+            fetched_at: now(),
+            servers: self.servers.clone(),
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.fetched_at))
+    }
+}
@@ -0,0 +1,596 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use futures::future::join_all;
+use serde_json::json;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use reqwest::{Client, header::*, Url, IntoUrl, Method, StatusCode};
+use lazy_static::lazy_static;
+use thiserror::Error;
+
+mod cache;
+mod advertise;
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+use cache::ServerCache;
+pub use advertise::{HostAdvertisement, Advertiser};
+
+lazy_static! {
+    pub static ref MASTER_SERVER_ADDR_DEF: &'static str = "http://ds3os-master.timleonard.uk:50020/";
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum ApiError {
+    #[error("Can't reach the master server after {attempts} attempt(s): {message}")]
+    Network {
+        message: String,
+        attempts: u32,
+    },
+    #[error("The master server didn't answer in time after {attempts} attempt(s)")]
+    Timeout {
+        attempts: u32,
+    },
+    #[error("The master server answered with HTTP {status} after {attempts} attempt(s)")]
+    Http {
+        status: StatusCode,
+        attempts: u32,
+    },
+    #[error("Can't decode the master server's response: {0}")]
+    Decode(String),
+    #[error("The master server reported '{status}': {message}")]
+    Server {
+        status: String,
+        message: String,
+    },
+    #[error("The master server returned no servers")]
+    Empty,
+    #[error("The master server doesn't support any known api version")]
+    UnsupportedVersion,
+    #[error("Invalid master server url: {0}")]
+    InvalidUrl(String),
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> ApiError {
+        if e.is_timeout() {
+            ApiError::Timeout { attempts: 1 }
+        }
+        else if e.is_decode() {
+            ApiError::Decode(e.to_string())
+        }
+        else if let (true, Some(status)) = (e.is_status(), e.status()) {
+            ApiError::Http { status, attempts: 1 }
+        }
+        else if e.is_builder() {
+            ApiError::InvalidUrl(e.to_string())
+        }
+        else {
+            ApiError::Network { message: e.to_string(), attempts: 1 }
+        }
+    }
+}
+
+impl ApiError {
+    // Errors worth another try, the master may recover from them
+    fn is_transient(&self) -> bool {
+        match self {
+            ApiError::Network { .. } | ApiError::Timeout { .. } => true,
+            ApiError::Http { status, .. } => status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS,
+            _ => false,
+        }
+    }
+
+    fn with_attempts(mut self, n: u32) -> ApiError {
+        match &mut self {
+            ApiError::Network { attempts, .. }
+            | ApiError::Timeout { attempts }
+            | ApiError::Http { attempts, .. } => *attempts = n,
+            _ => {},
+        }
+        self
+    }
+}
+
+/// How requests to a master server are timed out and retried.
+#[derive(Debug, Clone)]
+pub struct RequestPolicy {
+    pub connect_timeout: Duration,
+    // reqwest bounds the whole response rather than each read
+    pub read_timeout: Duration,
+    // Only GET requests are retried, they are the only idempotent ones we send
+    pub max_retries: u32,
+    pub backoff_base: Duration,
+    pub backoff_max: Duration,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        RequestPolicy {
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(15),
+            max_retries: 3,
+            backoff_base: Duration::from_millis(500),
+            backoff_max: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RequestPolicy {
+    // Exponential backoff with "equal jitter": half of the delay is fixed, the other half random
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self.backoff_base.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let delay = exp.min(self.backoff_max);
+        delay / 2 + delay.mul_f64(rand::random::<f64>() / 2.0)
+    }
+}
+
+pub type ApiResult<T> = std::result::Result<T, ApiError>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+    V2,
+    Unknown
+}
+
+impl ApiVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V2 => "v2",
+            ApiVersion::Unknown => ""
+        }
+    }
+}
+
+impl From<u32> for ApiVersion {
+    fn from(n: u32) -> ApiVersion {
+        match n {
+            1 => ApiVersion::V1,
+            2 => ApiVersion::V2,
+            _ => ApiVersion::Unknown
+        }
+    }
+}
+
+impl From<&str> for ApiVersion {
+    fn from(s: &str) -> ApiVersion {
+        let ss = String::from(s);
+        if ss.contains("1") {
+            ApiVersion::V1
+        }
+        else if ss.contains("2") {
+            ApiVersion::V2
+        }
+        else {
+            ApiVersion::Unknown
+        }
+    }
+}
+
+impl From<ApiVersion> for String {
+    fn from(val: ApiVersion) -> Self {
+        val.as_str().to_string()
+    }
+}
+
+// Error replies of both versions share these keys
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    message: String,
+}
+
+// Both api versions answer in this shape, the one the DS3OS master has always used
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiResponse {
+    status: String,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    servers: Vec<Server>,
+    #[serde(rename = "PublicKey", default)]
+    public_key: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Server {
+    #[serde(rename = "IpAddress", default)]
+    pub ip_addr: String,
+    #[serde(rename = "Hostname")]
+    pub hostname: String,
+    #[serde(rename = "PrivateHostname", default)]
+    pub private_hostname: String,
+    #[serde(rename = "Description", default)]
+    pub description: String,
+    #[serde(rename = "Name", default)]
+    pub name: String,
+    #[serde(rename = "PlayerCount", default)]
+    pub player_count: u32,
+    #[serde(rename = "PasswordRequired", default)]
+    pub password_required: bool,
+    #[serde(rename = "Password", default)]
+    pub passwd: String,
+    #[serde(rename = "ModsWhiteList", default)]
+    pub mods_white_list: String,
+    #[serde(rename = "ModsBlackList", default)]
+    pub mods_black_list: String,
+    #[serde(rename = "ModsRequiredList", default)]
+    pub mods_required_list: String,
+    #[serde(rename = "PublicKey", default)]
+    pub pubkey: String,
+
+    // Not every master reports these, they stay empty when missing
+    #[serde(rename = "GameType", default)]
+    pub game_type: String,
+    #[serde(rename = "Version", default)]
+    pub version: String,
+    #[serde(rename = "Port", default)]
+    pub port: u16,
+    #[serde(rename = "AllowSharding", default)]
+    pub allow_sharding: bool,
+    #[serde(rename = "WebAddress", default)]
+    pub web_address: String,
+
+    // The master server this entry was listed by, empty for imported servers
+    #[serde(skip)]
+    pub master: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerListing {
+    pub servers: Vec<Server>,
+    // Age of the oldest cached list used in place of an unreachable master
+    pub stale: Option<Duration>,
+    // What went wrong along the way without failing the listing, for the caller to show
+    pub problems: Vec<String>,
+}
+
+// Answer to a single request
+pub enum Reply<R> {
+    // The master has no route for this api version
+    Unsupported,
+    // A conditional request matched our cached copy
+    NotModified,
+    Body(R, HeaderMap),
+}
+
+#[derive(Clone)]
+pub struct MasterServerApi {
+    base_url: Url,
+    http_client: Client,
+    policy: RequestPolicy,
+    version: ApiVersion,
+    // The version the master actually answered with, shared between clones
+    negotiated: Arc<Mutex<Option<ApiVersion>>>,
+    cache_path: Option<PathBuf>,
+}
+
+impl MasterServerApi {
+    /// `api_url` may be the bare master address or a legacy `.../api/v1/servers/` url,
+    /// `ApiVersion::Unknown` lets the client negotiate the newest version the master speaks.
+    pub fn new<U, V>(api_url: U, version: V) -> ApiResult<MasterServerApi>
+    where
+        U: IntoUrl,
+        V: Into<ApiVersion>,
+    {
+        let policy = RequestPolicy::default();
+        let http_client = Self::build_client(&policy);
+
+        let base_url = base_url(api_url.into_url()?);
+
+        Ok(MasterServerApi {
+            cache_path: cache::default_path(&base_url),
+            base_url,
+            http_client,
+            policy,
+            version: version.into(),
+            negotiated: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Where the last successful server list is kept, `None` disables the cache.
+    pub fn with_cache(mut self, cache_path: Option<PathBuf>) -> Self {
+        self.cache_path = cache_path;
+        self
+    }
+
+    pub fn with_policy(mut self, policy: RequestPolicy) -> Self {
+        self.http_client = Self::build_client(&policy);
+        self.policy = policy;
+        self
+    }
+
+    fn build_client(policy: &RequestPolicy) -> Client {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+        Client::builder()
+            .default_headers(headers)
+            .connect_timeout(policy.connect_timeout)
+            .timeout(policy.read_timeout)
+            .build()
+            .unwrap_or_default()
+    }
+
+    fn candidate_versions(&self) -> Vec<ApiVersion> {
+        if let Some(v) = *self.negotiated.lock().unwrap() {
+            return vec![v];
+        }
+        match self.version {
+            ApiVersion::V1 => vec![ApiVersion::V1],
+            ApiVersion::V2 | ApiVersion::Unknown => vec![ApiVersion::V2, ApiVersion::V1],
+        }
+    }
+
+    pub fn endpoint(&self, version: ApiVersion, path: &str) -> ApiResult<Url> {
+        self.base_url
+            .join(&format!("api/{}/servers/{}", version.as_str(), path))
+            .map_err(|e| ApiError::InvalidUrl(e.to_string()))
+    }
+
+    pub async fn request<S, R>(&self, method: Method, url: &Url, headers: HeaderMap, request_body: Option<S>) -> ApiResult<Reply<R>>
+    where
+        S: Serialize,
+        R: DeserializeOwned,
+    {
+        let retries = if method == Method::GET { self.policy.max_retries } else { 0 };
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match self.request_once(method.clone(), url, headers.clone(), request_body.as_ref()).await {
+                Err(e) if attempt <= retries && e.is_transient() => {
+                    tokio::time::sleep(self.policy.backoff(attempt)).await;
+                },
+                res => return res.map_err(|e| e.with_attempts(attempt)),
+            }
+        }
+    }
+
+    async fn request_once<S, R>(&self, method: Method, url: &Url, headers: HeaderMap, request_body: Option<&S>) -> ApiResult<Reply<R>>
+    where
+        S: Serialize,
+        R: DeserializeOwned,
+    {
+        let mut builder = self.http_client.request(method, url.clone()).headers(headers);
+
+        if let Some(r) = request_body {
+            builder = builder.json(r);
+        }
+        let res = builder.send().await?;
+        let status = res.status();
+
+        if status == StatusCode::NOT_MODIFIED {
+            return Ok(Reply::NotModified);
+        }
+        let headers = res.headers().clone();
+        let body = res.bytes().await?;
+
+        // Masters that don't know a protocol version have no route for it. A route that
+        // exists but can't find what was asked for answers with its own error instead.
+        if matches!(status, StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED)
+            && !serde_json::from_slice::<ErrorResponse>(&body).is_ok_and(|err| !err.message.is_empty())
+        {
+            return Ok(Reply::Unsupported);
+        }
+
+        if !status.is_success() {
+            return Err(match serde_json::from_slice::<ErrorResponse>(&body) {
+                Ok(err) if !err.message.is_empty() => ApiError::Server { status: err.status, message: err.message },
+                _ => ApiError::Http { status, attempts: 1 },
+            });
+        }
+        serde_json::from_slice::<R>(&body)
+            .map(|r| Reply::Body(r, headers))
+            .map_err(|e| ApiError::Decode(e.to_string()))
+    }
+
+    // Send the request to the newest usable version, falling back to older ones when the master lacks the route.
+    async fn request_versioned<S>(&self, method: Method, path: &str, headers: HeaderMap, request_body: Option<S>) -> ApiResult<Reply<ApiResponse>>
+    where
+        S: Serialize,
+    {
+        for version in self.candidate_versions() {
+            let url = self.endpoint(version, path)?;
+            let res = self.request(method.clone(), &url, headers.clone(), request_body.as_ref()).await?;
+            if !matches!(res, Reply::Unsupported) {
+                *self.negotiated.lock().unwrap() = Some(version);
+                return Ok(res);
+            }
+        }
+        Err(ApiError::UnsupportedVersion)
+    }
+
+    /// Fetch the server list, answering from the cache when the master is unchanged or unreachable.
+    pub async fn list_servers(self) -> ApiResult<ServerListing> {
+        let cache = self.cache_path.as_deref().and_then(ServerCache::load);
+        let headers = cache.as_ref().map(ServerCache::conditional_headers).unwrap_or_default();
+
+        let res = match self.request_versioned::<String>(Method::GET, "", headers, None).await {
+            Ok(Reply::Body(res, headers)) => {
+                if res.status != "success" {
+                    Err(ApiError::Server { status: res.status, message: res.message })
+                }
+                else if res.servers.is_empty() {
+                    Err(ApiError::Empty)
+                }
+                else {
+                    Ok(ServerCache::new(res.servers, &headers))
+                }
+            },
+            Ok(Reply::NotModified) => match cache {
+                Some(ref cache) => Ok(cache.touched()),
+                None => Err(ApiError::Http { status: StatusCode::NOT_MODIFIED, attempts: 1 }),
+            },
+            Ok(Reply::Unsupported) => Err(ApiError::UnsupportedVersion),
+            Err(e) => Err(e),
+        };
+
+        let master = self.base_url.to_string();
+        let with_master = |servers: Vec<Server>| servers
+            .into_iter()
+            .map(|server| Server { master: master.clone(), ..server })
+            .collect();
+
+        match (res, cache) {
+            (Ok(fresh), _) => {
+                let mut problems = Vec::new();
+                if let Some(path) = &self.cache_path {
+                    if let Err(e) = fresh.save(path) {
+                        problems.push(format!("Can't save server list cache '{}': {}", path.to_string_lossy(), e));
+                    }
+                }
+                Ok(ServerListing { servers: with_master(fresh.servers), stale: None, problems })
+            },
+            // An empty list is a valid answer, not a reason to show old servers
+            (Err(ApiError::Empty), _) => Err(ApiError::Empty),
+            (Err(e), Some(cache)) => Ok(ServerListing {
+                stale: Some(cache.age()),
+                problems: vec![format!("Master server '{}' failed, using cached list: {}", master, e)],
+                servers: with_master(cache.servers),
+            }),
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    pub async fn get_pubkey(&self, ip_addr: &str, password: &str) -> ApiResult<String> {
+        let req_body = json!({
+            "password": password,
+        });
+        // Once a version answered, a bare 404 is about the server rather than the route
+        let known_version = self.negotiated.lock().unwrap().is_some();
+        let path = format!("{}/public_key", ip_addr);
+        let res = match self.request_versioned(Method::POST, &path, HeaderMap::new(), Some(&req_body)).await {
+            Ok(Reply::Body(res, _)) => res,
+            Err(ApiError::UnsupportedVersion) if known_version => return Err(ApiError::Server {
+                status: "error".into(),
+                message: format!("The master doesn't list a server at '{}'", ip_addr),
+            }),
+            Ok(Reply::NotModified) => return Err(ApiError::Http { status: StatusCode::NOT_MODIFIED, attempts: 1 }),
+            Ok(Reply::Unsupported) => return Err(ApiError::UnsupportedVersion),
+            Err(e) => return Err(e),
+        };
+        if res.status == "success" && !res.public_key.is_empty() {
+            Ok(res.public_key)
+        }
+        else {
+            Err(ApiError::Server { status: res.status, message: res.message })
+        }
+    }
+}
+
+/// The root of a master server, whether `url` points at it or at one of its endpoints.
+pub fn base_url(mut url: Url) -> Url {
+    let path = url.path().to_string();
+    if let Some(pos) = path.find("/api/") {
+        url.set_path(&path[..pos + 1]);
+    }
+    else if !path.ends_with('/') {
+        url.set_path(&format!("{}/", path));
+    }
+    url
+}
+
+/// A set of master servers queried together, earlier masters take precedence
+/// when several of them list the same hostname.
+#[derive(Clone)]
+pub struct MasterServerPool {
+    masters: Vec<MasterServerApi>,
+}
+
+impl MasterServerPool {
+    pub fn new<I, U>(api_urls: I) -> ApiResult<MasterServerPool>
+    where
+        I: IntoIterator<Item = U>,
+        U: IntoUrl,
+    {
+        let mut masters = api_urls
+            .into_iter()
+            .map(|url| MasterServerApi::new(url, ApiVersion::Unknown))
+            .collect::<ApiResult<Vec<_>>>()?;
+
+        if masters.is_empty() {
+            masters.push(MasterServerApi::new(*MASTER_SERVER_ADDR_DEF, ApiVersion::Unknown)?);
+        }
+        // The same master given twice would only be asked twice, keep its first place
+        let mut seen = HashSet::new();
+        masters.retain(|api| seen.insert(api.base_url.clone()));
+
+        Ok(MasterServerPool { masters })
+    }
+
+    pub fn with_policy(self, policy: RequestPolicy) -> Self {
+        MasterServerPool {
+            masters: self.masters.into_iter().map(|api| api.with_policy(policy.clone())).collect(),
+        }
+    }
+
+    pub fn masters(&self) -> &[MasterServerApi] {
+        &self.masters
+    }
+
+    pub fn without_cache(self) -> Self {
+        MasterServerPool {
+            masters: self.masters.into_iter().map(|api| api.with_cache(None)).collect(),
+        }
+    }
+
+    /// Query every master in parallel and merge the results, it only fails when all of them do.
+    pub async fn list_servers(self) -> ApiResult<ServerListing> {
+        let results = join_all(self.masters.iter().cloned().map(|api| api.list_servers())).await;
+
+        let mut servers: Vec<Server> = Vec::new();
+        let mut stale: Option<Duration> = None;
+        let mut problems = Vec::new();
+        let mut first_err = None;
+        let mut any_ok = false;
+        for res in results {
+            match res {
+                Ok(listing) => {
+                    any_ok = true;
+                    stale = stale.max(listing.stale);
+                    problems.extend(listing.problems);
+                    for server in listing.servers {
+                        if !servers.iter().any(|s| s.hostname.eq_ignore_ascii_case(&server.hostname)) {
+                            servers.push(server);
+                        }
+                    }
+                },
+                Err(ApiError::Empty) => any_ok = true,
+                Err(e) => {
+                    problems.push(format!("Master server error: {}", e));
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+
+        match (any_ok, first_err) {
+            (false, Some(e)) => Err(e),
+            _ if servers.is_empty() => Err(ApiError::Empty),
+            _ => Ok(ServerListing { servers, stale, problems }),
+        }
+    }
+
+    /// Ask the master which listed the server, imported servers try every master in order.
+    pub async fn get_pubkey(&self, server: &Server, password: &str) -> ApiResult<String> {
+        if let Some(api) = self.masters.iter().find(|api| api.base_url.as_str() == server.master) {
+            return api.get_pubkey(&server.ip_addr, password).await;
+        }
+
+        let mut first_err = None;
+        for api in &self.masters {
+            match api.get_pubkey(&server.ip_addr, password).await {
+                Ok(key) => return Ok(key),
+                Err(e) => { first_err.get_or_insert(e); },
+            }
+        }
+        Err(first_err.unwrap_or(ApiError::UnsupportedVersion))
+    }
+}
@@ -0,0 +1,466 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use futures::FutureExt;
+use futures::channel::oneshot;
+
+use super::*;
+use super::mock::{MockMaster, MockResponse};
+
+const V1_SERVERS: &str = r#"{
+    "status": "success",
+    "servers": [
+        {
+            "IpAddress": "203.0.113.1",
+            "Hostname": "ds3.example.com",
+            "PrivateHostname": "10.0.0.1",
+            "Description": "First server",
+            "Name": "Example",
+            "PlayerCount": 12,
+            "PasswordRequired": true,
+            "ModsWhiteList": "",
+            "ModsBlackList": "",
+            "ModsRequiredList": ""
+        },
+        {
+            "IpAddress": "203.0.113.2",
+            "Hostname": "other.example.com",
+            "Name": "Other"
+        }
+    ]
+}"#;
+
+// v2 answers come in the v1 shape, entries may carry a few more fields
+const V2_SERVERS: &str = r#"{
+    "status": "success",
+    "servers": [
+        {
+            "IpAddress": "203.0.113.3",
+            "Hostname": "v2.example.com",
+            "Name": "Version two",
+            "PlayerCount": 3,
+            "GameType": "DarkSouls3",
+            "Version": "0.9.0",
+            "Port": 50050,
+            "AllowSharding": true,
+            "WebAddress": "http://v2.example.com:50005"
+        }
+    ]
+}"#;
+
+const PUBKEY: &str = "-----BEGIN RSA PUBLIC KEY-----\nMIIBCgKCAQEA\n-----END RSA PUBLIC KEY-----\n";
+
+fn fast_policy() -> RequestPolicy {
+    RequestPolicy {
+        connect_timeout: Duration::from_secs(2),
+        read_timeout: Duration::from_secs(2),
+        max_retries: 2,
+        backoff_base: Duration::from_millis(1),
+        backoff_max: Duration::from_millis(5),
+    }
+}
+
+fn api<V: Into<ApiVersion>>(mock: &MockMaster, version: V) -> MasterServerApi {
+    MasterServerApi::new(mock.url(), version)
+        .unwrap()
+        .with_cache(None)
+        .with_policy(fast_policy())
+}
+
+fn temp_cache(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ds3os-loader-test-{}-{}.json", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn deserialize_v1_response() {
+    let res: ApiResponse = serde_json::from_str(V1_SERVERS).unwrap();
+    assert_eq!(res.status, "success");
+    assert_eq!(res.servers.len(), 2);
+
+    let server = &res.servers[0];
+    assert_eq!(server.ip_addr, "203.0.113.1");
+    assert_eq!(server.hostname, "ds3.example.com");
+    assert_eq!(server.private_hostname, "10.0.0.1");
+    assert_eq!(server.player_count, 12);
+    assert!(server.password_required);
+    assert!(server.pubkey.is_empty());
+
+    // Missing optional keys fall back to their defaults
+    assert_eq!(res.servers[1].player_count, 0);
+    assert!(res.servers[1].description.is_empty());
+}
+
+#[test]
+fn deserialize_v2_response() {
+    let res: ApiResponse = serde_json::from_str(V2_SERVERS).unwrap();
+    let server = &res.servers[0];
+    assert_eq!(server.ip_addr, "203.0.113.3");
+    assert_eq!(server.game_type, "DarkSouls3");
+    assert_eq!(server.version, "0.9.0");
+    assert_eq!(server.port, 50050);
+    assert!(server.allow_sharding);
+    assert_eq!(server.web_address, "http://v2.example.com:50005");
+}
+
+#[test]
+fn deserialize_imported_config() {
+    let server: Server = serde_json::from_str(r#"{"Hostname": "a.example.com", "PublicKey": "key", "Password": "pw"}"#).unwrap();
+    assert_eq!(server.hostname, "a.example.com");
+    assert_eq!(server.pubkey, "key");
+    assert_eq!(server.passwd, "pw");
+    assert!(server.master.is_empty());
+}
+
+#[test]
+fn legacy_url_is_reduced_to_base() {
+    let api = MasterServerApi::new("http://master.example.com:50020/api/v1/servers/", 1).unwrap();
+    assert_eq!(
+        api.endpoint(ApiVersion::V2, "1.2.3.4/public_key").unwrap().as_str(),
+        "http://master.example.com:50020/api/v2/servers/1.2.3.4/public_key"
+    );
+}
+
+#[tokio::test]
+async fn list_servers_v1() {
+    let mock = MockMaster::start(|req| match req.path.as_str() {
+        "/api/v1/servers/" => MockResponse::json(200, V1_SERVERS),
+        _ => MockResponse::not_found(),
+    }).await;
+
+    let listing = api(&mock, ApiVersion::V1).list_servers().await.unwrap();
+    assert_eq!(listing.servers.len(), 2);
+    assert!(listing.stale.is_none());
+    assert!(listing.servers.iter().all(|s| s.master == mock.url()));
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn negotiates_v2() {
+    let mock = MockMaster::start(|req| match req.path.as_str() {
+        "/api/v2/servers/" => MockResponse::json(200, V2_SERVERS),
+        _ => MockResponse::not_found(),
+    }).await;
+
+    let api = api(&mock, ApiVersion::Unknown);
+    let listing = api.clone().list_servers().await.unwrap();
+    assert_eq!(listing.servers[0].hostname, "v2.example.com");
+
+    // Later requests go straight to the negotiated version
+    api.list_servers().await.unwrap();
+    assert!(mock.requests().iter().all(|r| r.path == "/api/v2/servers/"));
+}
+
+#[tokio::test]
+async fn falls_back_to_v1() {
+    let mock = MockMaster::start(|req| match req.path.as_str() {
+        "/api/v1/servers/" => MockResponse::json(200, V1_SERVERS),
+        _ => MockResponse::not_found(),
+    }).await;
+
+    let listing = api(&mock, ApiVersion::V2).list_servers().await.unwrap();
+    assert_eq!(listing.servers.len(), 2);
+
+    let paths: Vec<String> = mock.requests().into_iter().map(|r| r.path).collect();
+    assert_eq!(paths, ["/api/v2/servers/", "/api/v1/servers/"]);
+}
+
+#[tokio::test]
+async fn unsupported_version() {
+    let mock = MockMaster::start(|_| MockResponse::not_found()).await;
+
+    let err = api(&mock, ApiVersion::Unknown).list_servers().await.unwrap_err();
+    assert!(matches!(err, ApiError::UnsupportedVersion), "{:?}", err);
+}
+
+#[tokio::test]
+async fn get_pubkey() {
+    let mock = MockMaster::start(|req| {
+        if req.method == "POST" && req.path == "/api/v1/servers/203.0.113.1/public_key" {
+            if req.body.contains(r#""password":"secret""#) {
+                MockResponse::json(200, &serde_json::json!({ "status": "success", "PublicKey": PUBKEY }).to_string())
+            }
+            else {
+                MockResponse::json(200, r#"{"status": "error", "message": "Password was incorrect."}"#)
+            }
+        }
+        else {
+            MockResponse::not_found()
+        }
+    }).await;
+
+    let api = api(&mock, ApiVersion::V1);
+    assert_eq!(api.get_pubkey("203.0.113.1", "secret").await.unwrap(), PUBKEY);
+
+    match api.get_pubkey("203.0.113.1", "wrong").await.unwrap_err() {
+        ApiError::Server { status, message } => {
+            assert_eq!(status, "error");
+            assert_eq!(message, "Password was incorrect.");
+        },
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn get_pubkey_negotiates_on_the_key_route() {
+    let mock = MockMaster::start(|req| match (req.method.as_str(), req.path.as_str()) {
+        ("POST", "/api/v1/servers/203.0.113.1/public_key") => {
+            MockResponse::json(200, &serde_json::json!({ "status": "success", "PublicKey": PUBKEY }).to_string())
+        },
+        _ => MockResponse::not_found(),
+    }).await;
+
+    // No request just to find the version, the key request itself falls back
+    let api = api(&mock, ApiVersion::Unknown);
+    assert_eq!(api.get_pubkey("203.0.113.1", "").await.unwrap(), PUBKEY);
+    api.get_pubkey("203.0.113.1", "").await.unwrap();
+    let paths: Vec<String> = mock.requests().into_iter().map(|r| r.path).collect();
+    assert_eq!(paths, [
+        "/api/v2/servers/203.0.113.1/public_key",
+        "/api/v1/servers/203.0.113.1/public_key",
+        "/api/v1/servers/203.0.113.1/public_key",
+    ]);
+}
+
+#[tokio::test]
+async fn missing_server_isnt_an_old_api() {
+    let mock = MockMaster::start(|req| match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/api/v2/servers/") => MockResponse::json(200, V2_SERVERS),
+        _ => MockResponse::not_found(),
+    }).await;
+
+    // Once the list came from v2, the key route's 404 is about the server
+    let api = api(&mock, ApiVersion::Unknown);
+    api.clone().list_servers().await.unwrap();
+    let err = api.get_pubkey("203.0.113.9", "").await.unwrap_err();
+    assert!(matches!(err, ApiError::Server { .. }), "{:?}", err);
+    let paths: Vec<String> = mock.requests().into_iter().map(|r| r.path).collect();
+    assert_eq!(paths, ["/api/v2/servers/", "/api/v2/servers/203.0.113.9/public_key"]);
+}
+
+#[tokio::test]
+async fn not_found_with_message_is_reported() {
+    let mock = MockMaster::start(|_| MockResponse::json(404, r#"{"status": "error", "message": "Failed to find server."}"#)).await;
+
+    let err = api(&mock, ApiVersion::Unknown).get_pubkey("203.0.113.9", "").await.unwrap_err();
+    assert!(matches!(err, ApiError::Server { ref message, .. } if message == "Failed to find server."), "{:?}", err);
+}
+
+#[tokio::test]
+async fn server_error_is_retried() {
+    let mock = MockMaster::start(|_| MockResponse::json(500, "")).await;
+
+    match api(&mock, ApiVersion::V1).list_servers().await.unwrap_err() {
+        ApiError::Http { status, attempts } => {
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+            assert_eq!(attempts, 3);
+        },
+        e => panic!("unexpected error {:?}", e),
+    }
+    assert_eq!(mock.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn error_message_is_reported() {
+    let mock = MockMaster::start(|_| MockResponse::json(403, r#"{"status": "error", "message": "Banned"}"#)).await;
+
+    let err = api(&mock, ApiVersion::V1).list_servers().await.unwrap_err();
+    assert!(matches!(err, ApiError::Server { ref message, .. } if message == "Banned"), "{:?}", err);
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn post_is_not_retried() {
+    let mock = MockMaster::start(|_| MockResponse::json(503, "")).await;
+
+    let err = api(&mock, ApiVersion::V1).get_pubkey("203.0.113.1", "").await.unwrap_err();
+    assert!(matches!(err, ApiError::Http { attempts: 1, .. }), "{:?}", err);
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn malformed_body() {
+    let mock = MockMaster::start(|_| MockResponse::json(200, "{ not json")).await;
+
+    let err = api(&mock, ApiVersion::V1).list_servers().await.unwrap_err();
+    assert!(matches!(err, ApiError::Decode(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn empty_list() {
+    let mock = MockMaster::start(|_| MockResponse::json(200, r#"{"status": "success", "servers": []}"#)).await;
+
+    let err = api(&mock, ApiVersion::V1).list_servers().await.unwrap_err();
+    assert!(matches!(err, ApiError::Empty), "{:?}", err);
+}
+
+#[tokio::test]
+async fn slow_response_times_out() {
+    let mock = MockMaster::start(|_| MockResponse::json(200, V1_SERVERS).delayed(Duration::from_secs(2))).await;
+
+    let policy = RequestPolicy {
+        read_timeout: Duration::from_millis(200),
+        max_retries: 1,
+        ..fast_policy()
+    };
+    let err = api(&mock, ApiVersion::V1).with_policy(policy).list_servers().await.unwrap_err();
+    assert!(matches!(err, ApiError::Timeout { attempts: 2 }), "{:?}", err);
+}
+
+#[tokio::test]
+async fn unreachable_master() {
+    // Grab a free port and close it again so nothing is listening there
+    let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let api = MasterServerApi::new(format!("http://{}/", addr), 1)
+        .unwrap()
+        .with_cache(None)
+        .with_policy(fast_policy());
+
+    let err = api.list_servers().await.unwrap_err();
+    assert!(matches!(err, ApiError::Network { attempts: 3, .. }), "{:?}", err);
+}
+
+#[tokio::test]
+async fn stale_cache_on_failure() {
+    let cache = temp_cache("stale");
+
+    let up = MockMaster::start(|_| MockResponse::json(200, V1_SERVERS)).await;
+    api(&up, ApiVersion::V1).with_cache(Some(cache.clone())).list_servers().await.unwrap();
+
+    let down = MockMaster::start(|_| MockResponse::json(500, "")).await;
+    let listing = api(&down, ApiVersion::V1).with_cache(Some(cache.clone())).list_servers().await.unwrap();
+    assert_eq!(listing.servers.len(), 2);
+    assert!(listing.stale.is_some());
+    assert!(listing.servers.iter().all(|s| s.master == down.url()));
+
+    let _ = std::fs::remove_file(cache);
+}
+
+#[tokio::test]
+async fn conditional_request() {
+    let cache = temp_cache("etag");
+
+    let mock = MockMaster::start(|req| {
+        if req.header("If-None-Match") == Some("\"abc\"") {
+            MockResponse::json(304, "")
+        }
+        else {
+            MockResponse::json(200, V1_SERVERS).header("ETag", "\"abc\"")
+        }
+    }).await;
+
+    let api = api(&mock, ApiVersion::V1).with_cache(Some(cache.clone()));
+    api.clone().list_servers().await.unwrap();
+    let listing = api.list_servers().await.unwrap();
+    assert_eq!(listing.servers.len(), 2);
+    assert!(listing.stale.is_none());
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].header("If-None-Match"), Some("\"abc\""));
+
+    let _ = std::fs::remove_file(cache);
+}
+
+#[tokio::test]
+async fn pool_merges_by_hostname() {
+    let first = MockMaster::start(|req| match req.path.as_str() {
+        "/api/v1/servers/" => MockResponse::json(200, V1_SERVERS),
+        _ => MockResponse::not_found(),
+    }).await;
+    let second = MockMaster::start(|req| match req.path.as_str() {
+        "/api/v2/servers/" => MockResponse::json(200, &V2_SERVERS.replace("v2.example.com", "DS3.example.com")),
+        _ => MockResponse::not_found(),
+    }).await;
+    let down = MockMaster::start(|_| MockResponse::json(500, "")).await;
+
+    let pool = MasterServerPool::new([first.url(), second.url(), down.url()])
+        .unwrap()
+        .without_cache()
+        .with_policy(fast_policy());
+    let listing = pool.list_servers().await.unwrap();
+
+    assert_eq!(listing.servers.len(), 2);
+    assert_eq!(listing.servers[0].hostname, "ds3.example.com");
+    assert_eq!(listing.servers[0].master, first.url());
+}
+
+#[test]
+fn pool_drops_repeated_masters() {
+    let pool = MasterServerPool::new([
+        "http://a.example.com/",
+        "http://b.example.com/api/v2/servers/",
+        "http://a.example.com/api/v1/servers/",
+        "http://b.example.com/",
+    ]).unwrap();
+    let urls: Vec<_> = pool.masters.iter().map(|api| api.base_url.as_str()).collect();
+    assert_eq!(urls, ["http://a.example.com/", "http://b.example.com/"]);
+}
+
+#[tokio::test]
+async fn pool_fails_when_all_fail() {
+    let down = MockMaster::start(|_| MockResponse::json(200, "garbage")).await;
+
+    let pool = MasterServerPool::new([down.url()])
+        .unwrap()
+        .without_cache()
+        .with_policy(fast_policy());
+    assert!(matches!(pool.list_servers().await.unwrap_err(), ApiError::Decode(_)));
+}
+
+#[tokio::test]
+async fn advertise_lifecycle() {
+    let mock = MockMaster::start(|req| match (req.method.as_str(), req.path.as_str()) {
+        ("POST", "/api/v1/servers/") | ("DELETE", "/api/v1/servers/") => MockResponse::json(200, r#"{"status": "success"}"#),
+        _ => MockResponse::not_found(),
+    }).await;
+
+    let ad = HostAdvertisement {
+        name: "Mine".into(),
+        hostname: "mine.example.com".into(),
+        pubkey: PUBKEY.into(),
+        ..HostAdvertisement::default()
+    };
+    // Stop once the second heartbeat is on its way, it still gets sent
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let mut stop_tx = Some(stop_tx);
+    let mut heartbeats = 0;
+    let player_count = || {
+        heartbeats += 1;
+        if heartbeats == 2 {
+            let _ = stop_tx.take().unwrap().send(());
+        }
+        7
+    };
+    let advertiser = Advertiser::new(api(&mock, ApiVersion::V1), ad, Duration::from_millis(1));
+    advertiser.run(player_count, |e| panic!("{}", e), stop_rx.map(|_| ())).await.unwrap();
+
+    let requests = mock.requests();
+    let methods: Vec<&str> = requests.iter().map(|req| req.method.as_str()).collect();
+    assert_eq!(methods, ["POST", "POST", "POST", "DELETE"]);
+    assert!(requests[0].body.contains(r#""Hostname":"mine.example.com""#));
+    assert!(requests[0].body.contains(r#""PlayerCount":0"#));
+    assert!(requests[1].body.contains(r#""PlayerCount":7"#));
+    assert!(requests[2].body.contains(r#""PlayerCount":7"#));
+}
+
+#[tokio::test]
+async fn advertise_v2_uses_v1_shape() {
+    let mock = MockMaster::start(|req| match req.path.as_str() {
+        "/api/v2/servers/" => MockResponse::json(200, r#"{"status": "success"}"#),
+        _ => MockResponse::not_found(),
+    }).await;
+
+    let ad = HostAdvertisement { name: "Mine".into(), ..HostAdvertisement::default() };
+    api(&mock, ApiVersion::V2).register_server(&ad).await.unwrap();
+    assert!(mock.requests()[0].body.contains(r#""Name":"Mine""#));
+}
+
+#[tokio::test]
+async fn advertise_rejected() {
+    let mock = MockMaster::start(|_| MockResponse::json(400, r#"{"status": "error", "message": "Missing hostname"}"#)).await;
+
+    let err = api(&mock, ApiVersion::V1).register_server(&HostAdvertisement::default()).await.unwrap_err();
+    assert!(matches!(err, ApiError::Server { .. }), "{:?}", err);
+}
@@ -0,0 +1,763 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use clap::{Args, Subcommand};
+use futures::FutureExt;
+use futures::future::{join_all, select, Either};
+use futures::pin_mut;
+use serde::Serialize;
+use serde_json::json;
+use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::api::{Server, MasterServerPool, HostAdvertisement, Advertiser, ApiError};
+use crate::patch::{Patches, Verification, GameVersion, ServerInfo};
+use crate::watch::{self, GameWatcher, WatchEvent, WATCH_INTERVAL};
+use crate::offline::{self, Manifest};
+use crate::imported;
+use crate::settings::Settings;
+use crate::passwords::PasswordStore;
+use crate::inspect;
+use crate::deeplink;
+use crate::launcher::Launcher;
+use crate::profile::GameProfile;
+use crate::pubkey::{self, KnownKeys, Trust};
+
+pub const EXIT_CODES_HELP: &str = "EXIT CODES:
+    0    Success
+    1    Any other error
+    2    Invalid arguments
+    3    The game or the given instance isn't running
+    4    Several game instances are running and none was chosen
+    5    No such server
+    6    Some game instances couldn't be patched, restored or inspected
+    7    The master servers couldn't be reached or gave a bad answer
+    8    The server's public key changed since it was last used";
+
+/// Failures scripts may want to tell apart, see `exit_code`.
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("{0} isn't running")]
+    NotRunning(&'static str),
+    #[error("PID {0} isn't a running game instance")]
+    NoSuchInstance(u32),
+    #[error("Several game instances are running, pick one with --pid or use --all:\n{0}")]
+    SeveralInstances(String),
+    #[error("{0}")]
+    NoSuchServer(String),
+    #[error("{failed} of {total} game instances failed")]
+    InstancesFailed { failed: usize, total: usize },
+    #[error("The public key of '{hostname}' changed since it was last used, someone may be impersonating it.\n\
+        Previous key: {previous}\nCurrent key: {current}\n\
+        Only pass --trust-new-key if the server owner told you the key changed")]
+    KeyChanged { hostname: String, previous: String, current: String },
+}
+
+pub fn exit_code(e: &anyhow::Error) -> i32 {
+    match e.downcast_ref::<CliError>() {
+        Some(CliError::NotRunning(_)) | Some(CliError::NoSuchInstance(_)) => 3,
+        Some(CliError::SeveralInstances(_)) => 4,
+        Some(CliError::NoSuchServer(_)) => 5,
+        Some(CliError::InstancesFailed { .. }) => 6,
+        Some(CliError::KeyChanged { .. }) => 8,
+        None if e.downcast_ref::<ApiError>().is_some() => 7,
+        None => 1,
+    }
+}
+
+/// How results are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Human,
+    // One JSON document per result on stdout, everything else goes to stderr
+    Json,
+}
+
+impl Output {
+    fn print<T: Serialize>(self, value: &T, human: impl FnOnce(&T)) -> Result<()> {
+        match self {
+            Output::Human => human(value),
+            Output::Json => println!("{}", serde_json::to_string(value)?),
+        }
+        Ok(())
+    }
+
+    // Progress messages, kept out of the way of JSON output
+    fn note(self, text: &str) {
+        match self {
+            Output::Human => println!("{}", text),
+            Output::Json => eprintln!("{}", text),
+        }
+    }
+
+    pub fn report_error(self, e: &anyhow::Error) {
+        match self {
+            Output::Human => eprintln!("Error: {}", e),
+            Output::Json => println!("{}", json!({ "error": e.to_string(), "code": exit_code(e) })),
+        }
+    }
+}
+
+/// Which game instances to act on when several are running.
+#[derive(Args)]
+pub struct InstanceArgs {
+    /// Process ID of the game instance, see the `instances` command
+    #[clap(long)]
+    pid: Option<u32>,
+
+    /// Act on every running game instance
+    #[clap(long, conflicts_with = "pid")]
+    all: bool,
+}
+
+// Passwords given on the command line are wiped from memory once dropped
+fn secret(s: &str) -> Zeroizing<String> {
+    Zeroizing::new(s.to_string())
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// List the servers of the game, imported ones first
+    List,
+
+    /// Show everything known about a server
+    Show {
+        /// Hostname or name of the server
+        server: String,
+    },
+
+    /// Import .ds3osconfig files, their servers are listed along with the master servers' ones
+    Import {
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Keep the passwords the configs hold in the password store
+        #[clap(long)]
+        remember_passwords: bool,
+    },
+
+    /// Publish your own server on the master servers until interrupted
+    Advertise {
+        /// The server's .ds3osconfig file
+        config: PathBuf,
+
+        /// Password players need to join, overrides the one in the config file
+        #[clap(long, parse(from_str = secret))]
+        password: Option<Zeroizing<String>>,
+
+        /// Player count reported in every heartbeat
+        #[clap(long, default_value_t = 0)]
+        players: u32,
+
+        /// Seconds between heartbeats
+        #[clap(long, value_name = "SECS", default_value_t = 60)]
+        interval: u64,
+    },
+
+    /// Point the running game at a server, the last one patched when none is given
+    Patch {
+        /// The server's .ds3osconfig file
+        #[clap(long)]
+        config: Option<PathBuf>,
+
+        /// Hostname or name of a listed or imported server
+        #[clap(long, conflicts_with = "config")]
+        server: Option<String>,
+
+        /// Password of the server, needed to fetch the key of protected servers
+        #[clap(long, parse(from_str = secret))]
+        password: Option<Zeroizing<String>>,
+
+        /// Use the server even though its key changed since it was last used
+        #[clap(long)]
+        trust_new_key: bool,
+
+        /// Wait for the game to start, and patch it again every time it's restarted
+        #[clap(long, conflicts_with_all = &["pid", "all"])]
+        wait: bool,
+
+        #[clap(flatten)]
+        instances: InstanceArgs,
+    },
+
+    /// Start the game and point it at a server once it's up, the last one patched when none is given
+    Launch {
+        /// The server's .ds3osconfig file
+        #[clap(long)]
+        config: Option<PathBuf>,
+
+        /// Hostname or name of a listed or imported server
+        #[clap(long, conflicts_with = "config")]
+        server: Option<String>,
+
+        /// Password of the server, needed to fetch the key of protected servers
+        #[clap(long, parse(from_str = secret))]
+        password: Option<Zeroizing<String>>,
+
+        /// Use the server even though its key changed since it was last used
+        #[clap(long)]
+        trust_new_key: bool,
+    },
+
+    /// Put back the official server info in the running game
+    Unpatch {
+        #[clap(flatten)]
+        instances: InstanceArgs,
+    },
+
+    /// List the running game instances
+    Instances,
+
+    /// Show which server the running game points at
+    Inspect {
+        #[clap(flatten)]
+        instances: InstanceArgs,
+    },
+
+    /// Write a copy of the game executable that connects to a server, no loader needed to play
+    PatchExe {
+        /// Game executable to copy, it's left untouched
+        exe: PathBuf,
+
+        /// Where to write the copy, next to the original by default
+        #[clap(long)]
+        output: Option<PathBuf>,
+
+        /// The server's .ds3osconfig file
+        #[clap(long, required_unless_present = "server")]
+        config: Option<PathBuf>,
+
+        /// Hostname or name of a listed or imported server
+        #[clap(long, conflicts_with = "config")]
+        server: Option<String>,
+
+        /// Password of the server, needed to fetch the key of protected servers
+        #[clap(long, parse(from_str = secret))]
+        password: Option<Zeroizing<String>>,
+
+        /// Use the server even though its key changed since it was last used
+        #[clap(long)]
+        trust_new_key: bool,
+    },
+
+    /// Open ds3os:// links with this loader, Linux only
+    RegisterLinkHandler,
+
+    /// Turn a copy written by patch-exe back into the original executable
+    RestoreExe {
+        /// The manifest written next to the copy
+        manifest: PathBuf,
+
+        /// File to restore instead of the copy the manifest names
+        #[clap(long)]
+        target: Option<PathBuf>,
+    },
+}
+
+/// A server as the CLI prints it, without the password imported configs may hold.
+#[derive(Serialize)]
+struct ServerSummary<'a> {
+    name: &'a str,
+    hostname: &'a str,
+    description: &'a str,
+    player_count: u32,
+    password_required: bool,
+    game_type: &'a str,
+    version: &'a str,
+    web_address: &'a str,
+    mods_white_list: &'a str,
+    mods_black_list: &'a str,
+    mods_required_list: &'a str,
+    // Empty for imported servers
+    master: &'a str,
+    imported: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    known_fingerprint: Option<&'a str>,
+}
+
+impl<'a> ServerSummary<'a> {
+    fn new(server: &'a Server, imported: bool) -> Self {
+        ServerSummary {
+            name: &server.name,
+            hostname: &server.hostname,
+            description: &server.description,
+            player_count: server.player_count,
+            password_required: server.password_required,
+            game_type: &server.game_type,
+            version: &server.version,
+            web_address: &server.web_address,
+            mods_white_list: &server.mods_white_list,
+            mods_black_list: &server.mods_black_list,
+            mods_required_list: &server.mods_required_list,
+            master: &server.master,
+            imported,
+            fingerprint: None,
+            known_fingerprint: None,
+        }
+    }
+}
+
+/// What became of one game instance.
+#[derive(Serialize)]
+struct InstanceOutcome<T> {
+    pid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl<T> InstanceOutcome<T> {
+    fn new(pid: u32, result: Result<T>) -> Self {
+        match result {
+            Ok(result) => InstanceOutcome { pid, result: Some(result), error: None },
+            Err(e) => InstanceOutcome { pid, result: None, error: Some(e.to_string()) },
+        }
+    }
+
+    fn describe(&self, describe: impl Fn(u32, &T) -> String) -> String {
+        match (&self.result, &self.error) {
+            (Some(result), _) => describe(self.pid, result),
+            (None, e) => format!("Game {}: {}", self.pid, e.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+fn describe_patched(pid: u32, info: &ServerInfo) -> String {
+    format!("Game {}: patched, it now connects to '{}'", pid, info.hostname)
+}
+
+fn describe_restored(pid: u32, info: &ServerInfo) -> String {
+    format!("Game {}: restored, it points at '{}' again", pid, info.hostname)
+}
+
+pub fn run(command: Command, masters: MasterServerPool, launcher: Launcher, profile: &'static GameProfile, output: Output) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async move {
+        match command {
+            Command::List => {
+                let servers = list_servers(profile, &masters).await?;
+                let summaries: Vec<ServerSummary> = servers.iter().map(|(s, imported)| ServerSummary::new(s, *imported)).collect();
+                output.print(&summaries, |summaries| {
+                    println!("{:<32} {:<32} {:>7}", "NAME", "ADDRESS", "PLAYERS");
+                    for s in summaries {
+                        let flags = [(s.password_required, "password"), (s.imported, "imported")]
+                            .iter()
+                            .filter(|(set, _)| *set)
+                            .map(|(_, flag)| *flag)
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        println!("{:<32} {:<32} {:>7}  {}", s.name, s.hostname, s.player_count, flags);
+                    }
+                })
+            },
+            Command::Show { server } => {
+                let (server, imported) = find_server(profile, &masters, &server).await?;
+                let known_keys = KnownKeys::load();
+                let mut summary = ServerSummary::new(&server, imported);
+                summary.fingerprint = pubkey::fingerprint(&server.pubkey).ok();
+                summary.known_fingerprint = known_keys.get(&server.hostname);
+                output.print(&summary, |s| {
+                    println!("{} ({})", s.name, s.hostname);
+                    if !s.description.is_empty() {
+                        println!("    {}", s.description);
+                    }
+                    let fields = [
+                        ("Players", s.player_count.to_string()),
+                        ("Password", if s.password_required { "required" } else { "none" }.to_string()),
+                        ("Game", s.game_type.to_string()),
+                        ("Version", s.version.to_string()),
+                        ("Website", s.web_address.to_string()),
+                        ("Mods whitelist", s.mods_white_list.to_string()),
+                        ("Mods blacklist", s.mods_black_list.to_string()),
+                        ("Mods required", s.mods_required_list.to_string()),
+                        ("Listed by", if s.imported { "imported".to_string() } else { s.master.to_string() }),
+                        ("Key fingerprint", s.fingerprint.clone().unwrap_or_default()),
+                        ("Trusted fingerprint", s.known_fingerprint.unwrap_or_default().to_string()),
+                    ];
+                    for (label, value) in fields.iter().filter(|(_, value)| !value.is_empty()) {
+                        println!("{:>20}: {}", label, value);
+                    }
+                })
+            },
+            Command::Import { files, remember_passwords } => {
+                let servers = files.iter().map(|file| imported::read_config(file)).collect::<Result<Vec<_>>>()?;
+                let mut settings = Settings::load();
+                settings.add_imported(&servers);
+                // Passwords that came with the configs are only remembered when asked to
+                if !remember_passwords && servers.iter().any(|s| !s.passwd.is_empty()) {
+                    eprintln!("Passwords in the configs aren't kept, import with --remember-passwords or give them with --password");
+                }
+                let with_password: Vec<&Server> = servers.iter().filter(|s| remember_passwords && !s.passwd.is_empty()).collect();
+                let mut store = if with_password.is_empty() { None } else { open_passwords("keep the imported ones") };
+                for server in with_password {
+                    match store.as_mut().map(|store| store.set(&server.hostname, &server.passwd)) {
+                        Some(Ok(())) => settings.set_remember_password(&server.hostname, true),
+                        Some(Err(e)) => eprintln!("Can't save the password of '{}': {}", server.hostname, e),
+                        None => eprintln!("The password of '{}' isn't kept, give it with --password", server.hostname),
+                    }
+                }
+                settings.save()?;
+                let summaries: Vec<ServerSummary> = servers.iter().map(|s| ServerSummary::new(s, true)).collect();
+                output.print(&summaries, |summaries| {
+                    for s in summaries {
+                        println!("Imported '{}' ({})", s.name, s.hostname);
+                    }
+                })
+            },
+            Command::Advertise { config, password, players, interval } => {
+                advertise(masters, config, password, players, Duration::from_secs(interval), output).await
+            },
+            Command::Patch { config, server, password, trust_new_key, wait, instances } => {
+                let info = resolve_info(profile, &masters, config, server, password, trust_new_key).await?;
+                if wait {
+                    wait_and_patch(profile, info, output).await
+                }
+                else {
+                    for_each_instance(profile, instances, output, |pid, version| patch(profile, pid, version, &info), describe_patched)
+                }
+            },
+            Command::Launch { config, server, password, trust_new_key } => {
+                let info = resolve_info(profile, &masters, config, server, password, trust_new_key).await?;
+                launch_and_patch(launcher, profile, info, output).await
+            },
+            Command::Unpatch { instances } => for_each_instance(profile, instances, output, unpatch, describe_restored),
+            Command::Inspect { instances } => {
+                // Without a list the server can still be told apart from the official one
+                let servers: Vec<Server> = match list_servers(profile, &masters).await {
+                    Ok(servers) => servers.into_iter().map(|(s, _)| s).collect(),
+                    Err(e) => {
+                        eprintln!("Can't fetch the server list: {}", e);
+                        Vec::new()
+                    },
+                };
+                for_each_instance(profile, instances, output, |pid, version| inspect::inspect(profile, pid, version, &servers), |_, i| i.to_string())
+            },
+            Command::Instances => {
+                let found = Patches::new(profile).find_processes();
+                if found.is_empty() {
+                    return Err(CliError::NotRunning(profile.name).into());
+                }
+                output.print(&found, |found| {
+                    for process in found {
+                        println!("{}", process);
+                        if let Some(exe) = &process.exe {
+                            println!("    {}", exe.to_string_lossy());
+                        }
+                        println!("    {}", process.cmdline.join(" "));
+                    }
+                })
+            },
+            Command::PatchExe { exe, output: out, config, server, password, trust_new_key } => {
+                let server = resolve_server(profile, &masters, config, server, password).await?;
+                pin_key(&server.hostname, &server.pubkey, trust_new_key)?;
+                let out = out.unwrap_or_else(|| offline::default_output(&exe));
+                let manifest = offline::patch_exe(profile, &exe, &out, &server.hostname, &server.pubkey)?;
+                let written = json!({
+                    "output": manifest.output,
+                    "manifest": Manifest::path_for(&manifest.output),
+                    "hostname": manifest.hostname,
+                });
+                output.print(&written, |_| println!("Wrote '{}' for '{}', manifest in '{}'",
+                    manifest.output.to_string_lossy(), manifest.hostname, Manifest::path_for(&manifest.output).to_string_lossy()))
+            },
+            Command::RegisterLinkHandler => {
+                deeplink::register()?;
+                // The GUI doesn't have to ask anymore
+                let mut settings = Settings::load();
+                settings.link_handler = Some(true);
+                if let Err(e) = settings.save() {
+                    eprintln!("Can't save settings: {}", e);
+                }
+                output.print(&json!({ "registered": deeplink::SCHEME }), |_| println!("{}:// links now open with this loader", deeplink::SCHEME))
+            },
+            Command::RestoreExe { manifest, target } => {
+                let restored = offline::restore_exe(&Manifest::load(&manifest)?, target.as_deref())?;
+                output.print(&json!({ "restored": restored }), |_| println!("Restored '{}'", restored.to_string_lossy()))
+            },
+        }
+    })
+}
+
+// Imported servers first, then those on the master servers, all for the chosen game.
+// The second field tells whether the server was imported.
+async fn list_servers(profile: &GameProfile, masters: &MasterServerPool) -> Result<Vec<(Server, bool)>> {
+    let mut servers: Vec<(Server, bool)> = Settings::load()
+        .imported
+        .iter()
+        .filter(|s| profile.lists(s))
+        .map(|s| (s.clone(), true))
+        .collect();
+
+    match masters.clone().list_servers().await {
+        Ok(listing) => {
+            for problem in &listing.problems {
+                eprintln!("{}", problem);
+            }
+            if let Some(age) = listing.stale {
+                eprintln!("Master servers unreachable, using the server list cached {} min ago", age.as_secs() / 60);
+            }
+            for server in listing.servers.into_iter().filter(|s| profile.lists(s)) {
+                if !servers.iter().any(|(s, _)| s.hostname.eq_ignore_ascii_case(&server.hostname)) {
+                    servers.push((server, false));
+                }
+            }
+        },
+        Err(ApiError::Empty) => {},
+        Err(e) if !servers.is_empty() => eprintln!("Can't fetch the server list: {}", e),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(servers)
+}
+
+async fn find_server(profile: &GameProfile, masters: &MasterServerPool, name: &str) -> Result<(Server, bool)> {
+    list_servers(profile, masters)
+        .await?
+        .into_iter()
+        .find(|(s, _)| s.hostname.eq_ignore_ascii_case(name) || s.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| CliError::NoSuchServer(format!("No {} server named '{}' is listed or imported", profile.name, name)).into())
+}
+
+// Find the server from a config file or the server list, with its public key
+async fn resolve_server(profile: &GameProfile, masters: &MasterServerPool, config: Option<PathBuf>, name: Option<String>, password: Option<Zeroizing<String>>) -> Result<Server> {
+    let mut server: Server = match (config, name) {
+        (Some(config), _) => imported::read_config(&config)?,
+        (None, Some(name)) => find_server(profile, masters, &name).await?.0,
+        (None, None) => return Err(CliError::NoSuchServer("No server given".into()).into()),
+    };
+    // The password only goes along with the request, the one from a config is moved out of `server`
+    let from_config = Zeroizing::new(std::mem::take(&mut server.passwd));
+    if server.pubkey.is_empty() {
+        let passwd = match password {
+            Some(passwd) => passwd,
+            None if !from_config.is_empty() => from_config,
+            None => remembered_password(&server.hostname).unwrap_or_default(),
+        };
+        server.pubkey = masters.get_pubkey(&server, &passwd).await?;
+    }
+    Ok(server)
+}
+
+// The password saved for `hostname` in the loader
+fn remembered_password(hostname: &str) -> Option<Zeroizing<String>> {
+    if !Settings::load().remembers_password(hostname) {
+        return None;
+    }
+    let store = open_passwords(&format!("use the one of '{}'", hostname))?;
+    store.get(hostname).unwrap_or_else(|e| {
+        eprintln!("Can't read the saved password of '{}': {}", hostname, e);
+        None
+    })
+}
+
+// The password store, unlocked with DS3OS_PASSPHRASE when it has to be. `purpose` tells
+// what the passphrase is needed for.
+fn open_passwords(purpose: &str) -> Option<PasswordStore> {
+    let mut store = PasswordStore::open();
+    if store.is_locked() {
+        let passphrase = match std::env::var("DS3OS_PASSPHRASE") {
+            Ok(passphrase) => Zeroizing::new(passphrase),
+            Err(_) => {
+                eprintln!("The saved passwords are locked, set DS3OS_PASSPHRASE to {}", purpose);
+                return None;
+            },
+        };
+        if let Err(e) = store.unlock(&passphrase) {
+            eprintln!("Can't unlock the saved passwords: {}", e);
+            return None;
+        }
+    }
+    Some(store)
+}
+
+// The server to patch to, the last one patched when none is given
+async fn resolve_info(profile: &GameProfile, masters: &MasterServerPool, config: Option<PathBuf>, name: Option<String>, password: Option<Zeroizing<String>>, trust_new_key: bool) -> Result<ServerInfo> {
+    let info = if config.is_none() && name.is_none() {
+        watch::load_last_server(profile)
+            .ok_or_else(|| CliError::NoSuchServer("No server given and none was patched before".into()))?
+    }
+    else {
+        let server = resolve_server(profile, masters, config, name, password).await?;
+        ServerInfo { hostname: server.hostname, pubkey: server.pubkey }
+    };
+    pin_key(&info.hostname, &info.pubkey, trust_new_key)?;
+    Ok(info)
+}
+
+// Same trust on first use as the GUI: a new hostname's key is remembered, a changed one
+// is refused unless `trust_new_key` says it's expected
+fn pin_key(hostname: &str, key: &str, trust_new_key: bool) -> Result<()> {
+    let fingerprint = pubkey::fingerprint(key)?;
+    let mut known_keys = KnownKeys::load();
+    match known_keys.check(hostname, &fingerprint) {
+        Trust::Known => return Ok(()),
+        Trust::New => {},
+        Trust::Changed { previous } if !trust_new_key => {
+            return Err(CliError::KeyChanged { hostname: hostname.to_string(), previous, current: fingerprint }.into());
+        },
+        Trust::Changed { .. } => {},
+    }
+    if let Err(e) = known_keys.remember(hostname, &fingerprint) {
+        eprintln!("Can't save known keys: {}", e);
+    }
+    Ok(())
+}
+
+fn patch(profile: &GameProfile, pid: u32, version: &GameVersion, info: &ServerInfo) -> Result<ServerInfo> {
+    let before = Patches::patch(pid, version, &info.hostname, &info.pubkey)?;
+    let report = Patches::verify(pid, version, &info.hostname, &info.pubkey, &before)?;
+    match report.result {
+        Verification::Matches => {
+            if let Err(e) = watch::save_last_server(profile, info) {
+                eprintln!("Can't remember the server: {}", e);
+            }
+            Ok(info.clone())
+        },
+        result => Err(anyhow!("Patch didn't stick: {:?}", result)),
+    }
+}
+
+async fn wait_and_patch(profile: &'static GameProfile, info: ServerInfo, output: Output) -> Result<()> {
+    let stop = tokio::signal::ctrl_c();
+    pin_mut!(stop);
+    let mut patches = Patches::new(profile);
+    let mut watcher = GameWatcher::new();
+    let mut last = None;
+
+    output.note(&format!("Waiting for the game to patch it to '{}', press Ctrl-C to stop", info.hostname));
+    loop {
+        let event = watcher.poll(&mut patches);
+        match &event {
+            WatchEvent::Ready(pid, version) => {
+                let outcome = InstanceOutcome::new(*pid, patch(profile, *pid, version, &info));
+                watcher.patched(*pid, outcome.error.is_none());
+                output.print(&outcome, |outcome| println!("{}", outcome.describe(describe_patched)))?;
+            },
+            WatchEvent::Starting(pid) if !matches!(last, Some(WatchEvent::Starting(_))) => {
+                output.note(&format!("Game {} found, waiting for it to initialize", pid));
+            },
+            WatchEvent::NotRunning => {
+                if let Some(WatchEvent::Patched(pid)) = last {
+                    output.note(&format!("Game {} exited, waiting for it to start again", pid));
+                }
+            },
+            _ => {},
+        }
+        last = Some(event);
+
+        let tick = tokio::time::sleep(WATCH_INTERVAL);
+        pin_mut!(tick);
+        if let Either::Left(_) = select(stop.as_mut(), tick).await {
+            return Ok(());
+        }
+    }
+}
+
+async fn launch_and_patch(launcher: Launcher, profile: &'static GameProfile, info: ServerInfo, output: Output) -> Result<()> {
+    let stop = tokio::signal::ctrl_c();
+    pin_mut!(stop);
+    let mut patches = Patches::new(profile);
+    let mut launch = launcher.launch(profile)?;
+    output.note(&format!("Launched {}", launch.describe()));
+
+    output.note(&format!("Waiting for the game to patch it to '{}', press Ctrl-C to stop", info.hostname));
+    loop {
+        if let WatchEvent::Ready(pid, version) = launch.poll(&mut patches)? {
+            let outcome = InstanceOutcome::new(pid, patch(profile, pid, &version, &info));
+            output.print(&outcome, |outcome| println!("{}", outcome.describe(describe_patched)))?;
+            return match outcome.error {
+                Some(_) => Err(CliError::InstancesFailed { failed: 1, total: 1 }.into()),
+                None => Ok(()),
+            };
+        }
+
+        let tick = tokio::time::sleep(WATCH_INTERVAL);
+        pin_mut!(tick);
+        if let Either::Left(_) = select(stop.as_mut(), tick).await {
+            return Ok(());
+        }
+    }
+}
+
+// Run `action` on the chosen instances and print what became of each, a lone instance needs no choosing
+fn for_each_instance<T, F, D>(profile: &'static GameProfile, instances: InstanceArgs, output: Output, mut action: F, describe: D) -> Result<()>
+where
+    T: Serialize,
+    F: FnMut(u32, &GameVersion) -> Result<T>,
+    D: Fn(u32, &T) -> String,
+{
+    let mut patches = Patches::new(profile);
+    let found = patches.find_processes();
+    let pids: Vec<u32> = match (instances.pid, instances.all) {
+        (Some(pid), _) if found.iter().any(|p| p.pid == pid) => vec![pid],
+        (Some(pid), _) => return Err(CliError::NoSuchInstance(pid).into()),
+        (None, true) => found.iter().map(|p| p.pid).collect(),
+        (None, false) if found.len() > 1 => {
+            let list: Vec<String> = found.iter().map(|p| p.to_string()).collect();
+            return Err(CliError::SeveralInstances(list.join("\n")).into());
+        },
+        (None, false) => found.iter().map(|p| p.pid).collect(),
+    };
+    if pids.is_empty() {
+        return Err(CliError::NotRunning(profile.name).into());
+    }
+
+    let outcomes: Vec<InstanceOutcome<T>> = pids
+        .iter()
+        .map(|&pid| InstanceOutcome::new(pid, patches.detect_version(pid).and_then(|version| action(pid, &version))))
+        .collect();
+    output.print(&outcomes, |outcomes| {
+        for outcome in outcomes {
+            println!("{}", outcome.describe(&describe));
+        }
+    })?;
+
+    let failed = outcomes.iter().filter(|o| o.error.is_some()).count();
+    if failed > 0 {
+        Err(CliError::InstancesFailed { failed, total: pids.len() }.into())
+    }
+    else {
+        Ok(())
+    }
+}
+
+fn unpatch(pid: u32, version: &GameVersion) -> Result<ServerInfo> {
+    let report = Patches::restore(pid, version)?;
+    match (report.result, report.found) {
+        (Verification::Matches, Some(info)) => Ok(info),
+        (Verification::Matches, None) => Err(anyhow!("Restored, but the block can't be decoded")),
+        (result, _) => Err(anyhow!("Restore didn't stick: {:?}", result)),
+    }
+}
+
+async fn advertise(masters: MasterServerPool, config: PathBuf, password: Option<Zeroizing<String>>, players: u32, interval: Duration, output: Output) -> Result<()> {
+    let mut server = imported::read_config(&config)?;
+    let mut ad = HostAdvertisement::from(&server);
+    // Only the advertisement keeps the password
+    server.passwd.zeroize();
+    if let Some(password) = password {
+        ad.passwd = password;
+    }
+
+    let stop = tokio::signal::ctrl_c().map(|_| ()).shared();
+    output.note(&format!("Advertising '{}' ({}), press Ctrl-C to stop", ad.name, ad.hostname));
+
+    let results = join_all(masters.masters().iter().map(|api| {
+        Advertiser::new(api.clone(), ad.clone(), interval)
+            .run(|| players, |e| eprintln!("Heartbeat failed: {}", e), stop.clone())
+    })).await;
+
+    let failed = results.iter().filter(|r| r.is_err()).count();
+    for err in results.into_iter().filter_map(|r| r.err()) {
+        eprintln!("Advertisement failed: {}", err);
+    }
+    if failed == masters.masters().len() {
+        Err(anyhow!("No master server accepted the advertisement"))
+    }
+    else {
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+// ds3os:// links, so a server can be joined from a link posted in a chat.
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use reqwest::Url;
+
+use crate::api::{self, Server};
+use crate::profile::Game;
+use crate::pubkey;
+
+pub const SCHEME: &str = "ds3os";
+
+/// A parsed `ds3os://connect?hostname=...` link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeepLink {
+    pub hostname: String,
+    pub name: Option<String>,
+    // PEM, already checked to be a key the game accepts
+    pub pubkey: Option<String>,
+    // URL of the master listing the server. The key is only fetched from it
+    // when it's one of the configured masters, links can't add masters.
+    pub master: Option<String>,
+    pub game: Option<Game>,
+}
+
+impl DeepLink {
+    pub fn is_link(arg: &str) -> bool {
+        arg.get(..SCHEME.len() + 1).is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{}:", SCHEME)))
+    }
+
+    pub fn parse(uri: &str) -> Result<Self> {
+        let url = Url::parse(uri).map_err(|e| anyhow!("Invalid link '{}': {}", uri, e))?;
+        if url.scheme() != SCHEME {
+            return Err(anyhow!("Not a {}:// link", SCHEME));
+        }
+        if url.host_str() != Some("connect") {
+            return Err(anyhow!("Unknown link action '{}'", url.host_str().unwrap_or_default()));
+        }
+
+        let mut link = DeepLink { hostname: String::new(), name: None, pubkey: None, master: None, game: None };
+        for (key, value) in url.query_pairs() {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match key.as_ref() {
+                "hostname" => link.hostname = value.to_string(),
+                "name" => link.name = Some(value.to_string()),
+                "pubkey" => link.pubkey = Some(decode_pubkey(value)?),
+                "master" => {
+                    let master = Url::parse(value).map_err(|e| anyhow!("Invalid master '{}': {}", value, e))?;
+                    if !matches!(master.scheme(), "http" | "https") {
+                        return Err(anyhow!("Master '{}' isn't an http(s) URL", value));
+                    }
+                    link.master = Some(api::base_url(master).to_string());
+                },
+                "game" => link.game = Some(value.parse()?),
+                // Newer links may carry more, they still work without it
+                _ => {},
+            }
+        }
+
+        if link.hostname.is_empty() {
+            return Err(anyhow!("Link has no hostname"));
+        }
+        if link.hostname.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(anyhow!("Invalid hostname '{}'", link.hostname));
+        }
+        Ok(link)
+    }
+
+    /// The server the link points at, as a row of the server list.
+    pub fn to_server(&self) -> Server {
+        Server {
+            ip_addr: self.hostname.clone(),
+            hostname: self.hostname.clone(),
+            private_hostname: String::new(),
+            description: String::new(),
+            name: self.name.clone().unwrap_or_else(|| self.hostname.clone()),
+            player_count: 0,
+            password_required: false,
+            passwd: String::new(),
+            mods_white_list: String::new(),
+            mods_black_list: String::new(),
+            mods_required_list: String::new(),
+            pubkey: self.pubkey.clone().unwrap_or_default(),
+            game_type: self.game.map(|game| game.profile().game_type.to_string()).unwrap_or_default(),
+            version: String::new(),
+            port: 0,
+            allow_sharding: false,
+            web_address: String::new(),
+            master: self.master.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// Links are often built by hand, accept both base64 alphabets with or without padding
+fn decode_pubkey(value: &str) -> Result<String> {
+    // `+` turns into a space when the link isn't percent encoded
+    let value = value.replace(' ', "+");
+    let bytes = STANDARD
+        .decode(&value)
+        .or_else(|_| URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')))
+        .map_err(|e| anyhow!("Public key isn't valid base64: {}", e))?;
+    let pem = String::from_utf8(bytes).map_err(|_| anyhow!("Public key isn't a PEM"))?;
+    pubkey::parse(&pem)?;
+    Ok(pem)
+}
+
+/// Make the desktop open ds3os:// links with this executable, through a .desktop file.
+/// Nothing is touched when it's already registered.
+#[cfg(target_os = "linux")]
+pub fn register() -> Result<()> {
+    use std::fs;
+    use std::process::Command;
+
+    let exe = std::env::current_exe()?;
+    let exe = exe.to_str().ok_or_else(|| anyhow!("The executable's path '{}' isn't valid UTF-8", exe.to_string_lossy()))?;
+    let dir = dirs::data_dir().ok_or_else(|| anyhow!("No data directory"))?.join("applications");
+    let path = dir.join("ds3os-loader.desktop");
+    let entry = format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=DS3OS Loader\n\
+         Comment=Play Dark Souls on community servers\n\
+         Exec={} %u\n\
+         Terminal=false\n\
+         NoDisplay=true\n\
+         MimeType=x-scheme-handler/{};\n",
+        desktop_exec_arg(exe)?, SCHEME
+    );
+    if fs::read_to_string(&path).is_ok_and(|current| current == entry) {
+        return Ok(());
+    }
+
+    fs::create_dir_all(&dir)?;
+    fs::write(&path, entry)?;
+    let result = Command::new("xdg-mime")
+        .args(["default", "ds3os-loader.desktop", &format!("x-scheme-handler/{}", SCHEME)])
+        .status()
+        .map_err(|e| anyhow!("Can't run xdg-mime: {}", e))
+        .and_then(|status| if status.success() { Ok(()) } else { Err(anyhow!("xdg-mime failed with {}", status)) });
+    // Without it the entry isn't the handler, try again next time
+    if result.is_err() {
+        let _ = fs::remove_file(&path);
+    }
+    result
+}
+
+// Quote `arg` for the Exec key of a .desktop file. Reserved characters get a backslash inside the
+// double quotes, and every backslash is doubled again since the key is read as a string value first.
+// A `%` would start a field code.
+#[cfg(any(target_os = "linux", test))]
+fn desktop_exec_arg(arg: &str) -> Result<String> {
+    if arg.chars().any(char::is_control) {
+        return Err(anyhow!("'{}' can't go in a desktop entry", arg.escape_debug()));
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '`' | '$' => { quoted.push_str("\\\\"); quoted.push(c); },
+            '\\' => quoted.push_str("\\\\\\\\"),
+            '%' => quoted.push_str("%%"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Ok(quoted)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn register() -> Result<()> {
+    Err(anyhow!("Registering the link handler is only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEM: &str = "-----BEGIN RSA PUBLIC KEY-----\n\
+        MEgCQQC255h0T2UmgLJBEm8CLFnCNJA73ZW+eUWK4I3rWqfyIKFrbt5+14UyLH+7\n\
+        cHqAkBqwG/PqA8n0qcYRl9ZmTPezAgMBAAE=\n\
+        -----END RSA PUBLIC KEY-----\n";
+
+    #[test]
+    fn parses_full_link() {
+        let key = STANDARD.encode(PEM);
+        let uri = format!(
+            "ds3os://connect?hostname=ds3.example.com&name=My%20Server&pubkey={}&master=https://master.example.com/api/v2/servers&game=ds3",
+            key.replace('+', "%2B").replace('=', "%3D").replace('/', "%2F")
+        );
+        let link = DeepLink::parse(&uri).unwrap();
+        assert_eq!(link.hostname, "ds3.example.com");
+        assert_eq!(link.name.as_deref(), Some("My Server"));
+        assert_eq!(link.pubkey.as_deref(), Some(PEM));
+        assert_eq!(link.master.as_deref(), Some("https://master.example.com/"));
+        assert_eq!(link.game, Some(Game::DarkSouls3));
+    }
+
+    #[test]
+    fn accepts_unescaped_and_url_safe_keys() {
+        let plain = format!("ds3os://connect?hostname=h&pubkey={}", STANDARD.encode(PEM));
+        assert_eq!(DeepLink::parse(&plain).unwrap().pubkey.as_deref(), Some(PEM));
+
+        let url_safe = format!("ds3os://connect?hostname=h&pubkey={}", URL_SAFE_NO_PAD.encode(PEM));
+        assert_eq!(DeepLink::parse(&url_safe).unwrap().pubkey.as_deref(), Some(PEM));
+    }
+
+    #[test]
+    fn quotes_exec_paths() {
+        assert_eq!(desktop_exec_arg("/opt/ds3os loader/bin").unwrap(), r#""/opt/ds3os loader/bin""#);
+        assert_eq!(desktop_exec_arg(r#"/a"b`c$d%e"#).unwrap(), r#""/a\\"b\\`c\\$d%%e""#);
+        assert_eq!(desktop_exec_arg(r"/a\b").unwrap(), r#""/a\\\\b""#);
+        assert!(desktop_exec_arg("/a\nb").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_links() {
+        for uri in [
+            "https://connect?hostname=h",
+            "ds3os://join?hostname=h",
+            "ds3os://connect?name=x",
+            "ds3os://connect?hostname=a%20b",
+            "ds3os://connect?hostname=h&pubkey=bm90IGEga2V5",
+            "ds3os://connect?hostname=h&master=file:///etc/passwd",
+            "ds3os://connect?hostname=h&game=ds9",
+        ] {
+            assert!(DeepLink::parse(uri).is_err(), "{}", uri);
+        }
+        assert!(DeepLink::is_link("DS3OS://connect?hostname=h"));
+        assert!(!DeepLink::is_link("list"));
+    }
+}
@@ -1,54 +1,149 @@
-use lazy_static::lazy_static;
 use bytes::{Bytes, BytesMut};
 
-lazy_static! {
-    pub static ref TEA_BLOCK_SIZE: usize = 8;
+extern "C" {
+    fn encrypt(v: *mut u32, k: *const u32);
+    fn decrypt(v: *mut u32, k: *const u32);
 }
 
-extern "C" {
-    fn set_key(k: *const u32);
-    fn encrypt(v: *mut u8);
+/// TEA with a fixed key, working on independent 8 byte blocks (ECB) of two little endian words.
+/// It holds nothing but the key, so one context can be shared between threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tea32 {
+    key: [u32; 4],
 }
 
-pub fn tea32_encrypt(data: &BytesMut, key: &[u32;4]) -> Bytes {
-    let length_rounded_to_block_size: usize = ((data.len() + (*TEA_BLOCK_SIZE - 1)) / *TEA_BLOCK_SIZE) * *TEA_BLOCK_SIZE;
+impl Tea32 {
+    pub const BLOCK_SIZE: usize = 8;
+
+    pub fn new(key: [u32; 4]) -> Self {
+        Tea32 { key }
+    }
 
-    let mut output: BytesMut = BytesMut::with_capacity(length_rounded_to_block_size);
-    output.extend_from_slice(&data[..]);
+    pub fn encrypt_block(&self, block: &mut [u8; 8]) {
+        self.apply(block, encrypt)
+    }
 
-    unsafe { set_key(key.as_ptr()); }
+    pub fn decrypt_block(&self, block: &mut [u8; 8]) {
+        self.apply(block, decrypt)
+    }
 
-    let mut block_offset: usize = 0;
-    while block_offset < output.len() {
-        tea32_encrypt_block(&mut output, block_offset);
-        block_offset += *TEA_BLOCK_SIZE;
+    // The C side works on words, hand it an aligned copy instead of a pointer into the bytes
+    fn apply(&self, block: &mut [u8; 8], f: unsafe extern "C" fn(*mut u32, *const u32)) {
+        let mut v = [
+            u32::from_le_bytes([block[0], block[1], block[2], block[3]]),
+            u32::from_le_bytes([block[4], block[5], block[6], block[7]]),
+        ];
+        unsafe { f(v.as_mut_ptr(), self.key.as_ptr()); }
+        block[..4].copy_from_slice(&v[0].to_le_bytes());
+        block[4..].copy_from_slice(&v[1].to_le_bytes());
     }
 
-    output.freeze()
-}
+    /// Encrypt `data`, zero padded up to a whole number of blocks.
+    pub fn encrypt(&self, data: &[u8]) -> Bytes {
+        let mut output = BytesMut::from(data);
+        output.resize(data.len().div_ceil(Self::BLOCK_SIZE) * Self::BLOCK_SIZE, 0);
+        self.for_each_block(&mut output, Self::encrypt_block);
+        output.freeze()
+    }
 
-pub fn tea32_encrypt_block(input: &mut BytesMut, input_offset: usize)
-{
-    unsafe {
-        encrypt(input.as_mut_ptr().offset(input_offset as isize));
+    /// Decrypt `data`, trailing bytes that don't fill a whole block are left as they are.
+    pub fn decrypt(&self, data: &[u8]) -> Bytes {
+        let mut output = BytesMut::from(data);
+        self.for_each_block(&mut output, Self::decrypt_block);
+        output.freeze()
+    }
+
+    fn for_each_block(&self, data: &mut [u8], f: fn(&Self, &mut [u8; 8])) {
+        for chunk in data.chunks_exact_mut(Self::BLOCK_SIZE) {
+            f(self, chunk.try_into().unwrap());
+        }
     }
 }
 
-// pub fn test() {
-//     let mut output = BytesMut::from_iter(&[3u8, 2u8, 1u8, 0u8, 7u8, 6u8, 5u8, 4u8]);
-//     unsafe { set_key([0u32;4].as_ptr()); }
-//     tea32_encrypt_block(&mut output, 0);
-//     println!("{:#?}", output.freeze().to_vec());
-    
-//     use tea_soft::block_cipher::generic_array::GenericArray;
-//     use tea_soft::block_cipher::{BlockCipher, NewBlockCipher};
-
-//     let key = GenericArray::from_slice(&[0u8;16]);
-//     let mut block = GenericArray::clone_from_slice(&[0u8, 1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8]);
-//     // Initialize cipher
-//     let cipher = tea_soft::Tea32::new(&key);
-
-//     // Encrypt block in-place
-//     cipher.encrypt_block(&mut block);
-//     println!("{:#?}", block.to_vec());
-// }
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::Tea32;
+
+    fn words(block: [u8; 8]) -> [u32; 2] {
+        [
+            u32::from_le_bytes([block[0], block[1], block[2], block[3]]),
+            u32::from_le_bytes([block[4], block[5], block[6], block[7]]),
+        ]
+    }
+
+    fn block(v: [u32; 2]) -> [u8; 8] {
+        let mut block = [0u8; 8];
+        block[..4].copy_from_slice(&v[0].to_le_bytes());
+        block[4..].copy_from_slice(&v[1].to_le_bytes());
+        block
+    }
+
+    // (key, plaintext, ciphertext)
+    const KNOWN_ANSWERS: [([u32; 4], [u32; 2], [u32; 2]); 2] = [
+        ([0, 0, 0, 0], [0, 0], [0x41EA3A0A, 0x94BAA940]),
+        ([0x00112233, 0x44556677, 0x8899AABB, 0xCCDDEEFF], [0x01234567, 0x89ABCDEF], [0x126C6B92, 0xC0653A3E]),
+    ];
+
+    #[test]
+    fn known_answers() {
+        for (key, plain, cipher) in KNOWN_ANSWERS {
+            let tea = Tea32::new(key);
+
+            let mut b = block(plain);
+            tea.encrypt_block(&mut b);
+            assert_eq!(words(b), cipher);
+
+            tea.decrypt_block(&mut b);
+            assert_eq!(words(b), plain);
+        }
+    }
+
+    #[test]
+    fn buffer_round_trip() {
+        let tea = Tea32::new([0x4B694CD6, 0x96ADA235, 0xEC91D9D4, 0x23F562E5]);
+        let data: Vec<u8> = (0..64).collect();
+
+        let encrypted = tea.encrypt(&data);
+        assert_eq!(encrypted.len(), data.len());
+        assert_ne!(&encrypted[..], &data[..]);
+        assert_eq!(&tea.decrypt(&encrypted)[..], &data[..]);
+    }
+
+    #[test]
+    fn buffer_is_independent_blocks() {
+        let (key, plain, cipher) = KNOWN_ANSWERS[1];
+        let tea = Tea32::new(key);
+        let data = [block(plain), block(plain)].concat();
+
+        let encrypted = tea.encrypt(&data);
+        assert_eq!(words(encrypted[..8].try_into().unwrap()), cipher);
+        assert_eq!(words(encrypted[8..].try_into().unwrap()), cipher);
+    }
+
+    #[test]
+    fn encrypt_pads_and_decrypt_keeps_tail() {
+        let tea = Tea32::new([1, 2, 3, 4]);
+
+        let encrypted = tea.encrypt(&[0xAA; 11]);
+        assert_eq!(encrypted.len(), 16);
+        assert_eq!(&tea.decrypt(&encrypted)[..], &[[0xAA; 11].as_slice(), &[0; 5]].concat()[..]);
+
+        // A partial block can't be decrypted, so it comes back untouched
+        let decrypted = tea.decrypt(&encrypted[..11]);
+        assert_eq!(&decrypted[8..], &encrypted[8..11]);
+    }
+
+    #[test]
+    fn concurrent_keys_dont_interfere() {
+        let threads: Vec<_> = (0..8u32)
+            .map(|i| std::thread::spawn(move || {
+                let tea = Tea32::new([i, i.wrapping_mul(3), i ^ 0xFFFF, !i]);
+                let data: Vec<u8> = (0..4096).map(|n| (n as u32 ^ i) as u8).collect();
+                (0..200).all(|_| tea.decrypt(&tea.encrypt(&data))[..] == data[..])
+            }))
+            .collect();
+        for thread in threads {
+            assert!(thread.join().unwrap());
+        }
+    }
+}
@@ -1,26 +1,77 @@
-use iced::{Application, executor, Command, Column, Font};
+use iced::{Application, executor, Command, Column, Container, Font, Subscription, Text};
+use iced_native::{window, Event};
 use iced_aw::{split, Split};
-use native_dialog::{FileDialog, MessageDialog};
-use std::io::BufReader;
+use native_dialog::{FileDialog, MessageDialog, MessageType};
 use anyhow::Result;
-use std::fs::File;
+use std::path::PathBuf;
+use zeroize::{Zeroize, Zeroizing};
 
-use crate::api::{Server, MasterServerApi};
-use crate::patch::Patches;
+use crate::api::{Server, MasterServerPool, ApiError};
+use crate::patch::{Patches, GameVersion, GameProcess, VerifyReport, Verification, ServerInfo, Detection};
 use crate::widgets::list::{ServerList, ListMessage, RowMessage};
-use crate::widgets::topbar::{TopBar, TopBarMessage};
+use crate::widgets::topbar::{TopBar, TopBarMessage, InstanceChoice};
 use crate::widgets::detail_panel::DetailPanel;
-use crate::localize::FAIL_REASON_LOCALIZED_STRING;
+use crate::localize::{FAIL_REASON_LOCALIZED_STRING, TEXT_LOCALIZED_STRING, TextType};
+use crate::pubkey::{self, KnownKeys, Trust};
+use crate::offline;
+use crate::imported;
+use crate::settings::Settings;
+use crate::passwords::{PasswordStore, PasswordError};
+use crate::inspect;
+use crate::launcher::{Launcher, Launch};
+use crate::profile::{Game, GameProfile};
+use crate::deeplink::{self, DeepLink};
+use crate::watch::{self, GameWatcher, WatchEvent, WatchStep, WATCH_INTERVAL};
 
 pub static ICON_FONT: Font = Font::External { 
     name: "Icons",
     bytes: include_bytes!("../resources/icons/icons.ttf"),
 };
 
+pub struct LoaderFlags {
+    pub masters: MasterServerPool,
+    pub launcher: Launcher,
+    pub game: Game,
+    // Server to select once the window opens
+    pub link: Option<DeepLink>,
+    // Start waiting for the game right away
+    pub auto_patch: bool,
+    pub settings: Settings,
+}
+
 pub struct LoaderMainInterface {
-    api: MasterServerApi,
+    api: MasterServerPool,
+    // Patches the game chosen in the top bar
     patch: Patches,
-    cur_passwd: String,
+    known_keys: KnownKeys,
+    settings: Settings,
+    // Server selected last time, until the master's list shows it
+    pending_selection: Option<String>,
+    // Settings are saved, the window can close
+    exiting: bool,
+    // Wiped from memory whenever it's replaced
+    cur_passwd: Zeroizing<String>,
+    // Opened the first time a password is remembered or recalled
+    passwords: Option<PasswordStore>,
+    // The password file has to be unlocked, the passphrase typed so far
+    asking_passphrase: bool,
+    passphrase: Zeroizing<String>,
+    // Saved or forgotten (None) once the password file is unlocked
+    pending_passwords: Vec<(String, Option<Zeroizing<String>>)>,
+    watcher: GameWatcher,
+    // A game version is being detected for the watcher or the launch, ticks wait for it
+    detecting: bool,
+    auto_patch: bool,
+    // Game instances seen on the last patch, and which of them to patch
+    instances: Vec<GameProcess>,
+    instance_choice: Option<InstanceChoice>,
+    // Applied by auto patch whenever the game starts
+    last_server: Option<ServerInfo>,
+    launcher: Launcher,
+    // A game we started and the server to patch it to once it's up
+    launching: Option<(Launch, ServerInfo)>,
+    // What auto patch is up to and problems not worth a dialog, shown under the top bar
+    status: Option<String>,
     // The local state of the two buttons
     topbar: TopBar,
     server_list: ServerList,
@@ -28,15 +79,59 @@ pub struct LoaderMainInterface {
     split_pane: split::State,
 }
 
+/// Where the server info of the chosen server gets written.
+#[derive(Debug, Clone)]
+pub enum PatchTarget {
+    // Every chosen game instance with its layout
+    Processes(Vec<(u32, GameVersion)>),
+    // The game we're about to start
+    Launch,
+    // A copy of this executable is written next to it
+    Exe(PathBuf),
+}
+
+/// What to do with the chosen game instances once their versions are known.
+#[derive(Debug, Clone, Copy)]
+pub enum InstanceAction {
+    Patch,
+    Restore,
+    Inspect,
+}
+
+/// Who waits for a game version being detected in the background.
+#[derive(Debug, Clone, Copy)]
+pub enum Watching {
+    Launch,
+    AutoPatch,
+}
+
+// What became of each game instance, by pid
+pub type InstanceResults = Vec<(u32, Result<VerifyReport, String>)>;
+
 #[derive(Debug, Clone)]
 pub enum Message {
     ListMessage(ListMessage),
     TopBarMessage(TopBarMessage),
     PasswordInput(String),
+    RememberPassword(bool),
+    PassphraseInput(String),
+    UnlockPasswords,
     Patch,
+    InstancesFound(InstanceAction, Result<Vec<(u32, GameVersion)>, (FailReason, String)>),
+    // The version found, None while the game is still starting
+    Detected(Watching, Detection, Option<GameVersion>),
+    // target, hostname, public key
+    PublicKeyFetched(PatchTarget, String, String),
+    Patched(InstanceResults),
+    AutoPatched(u32, Result<VerifyReport, String>),
+    ExePatched(PathBuf),
+    WatchTick,
+    Restored(InstanceResults),
+    Inspected(String),
     Fail(FailReason, String),
     OnResize(u16),
-    Nothing,
+    WindowResized(u32, u32),
+    CloseRequested,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -48,32 +143,101 @@ pub enum FailReason {
     FetchPublicKeyFail,
     ProcessNotFound,
     PatchFail,
+    InvalidPublicKey,
+    UnknownGameVersion,
+    PatchVerifyFail,
+    RestoreFail,
+    PatchExeFail,
+    ChooseInstance,
+    InspectFail,
+    LaunchFail,
+    PasswordStoreFail,
+    LinkHandlerFail,
+
+    MasterUnreachable,
+    MasterTimeout,
+    MasterHttpError,
+    MasterBadResponse,
+    MasterEmptyList,
+}
+
+impl FailReason {
+    /// Pick the most specific reason for a master server error,
+    /// `rejected` is used when the master itself refused the request.
+    pub fn from_api_error(e: &ApiError, rejected: FailReason) -> FailReason {
+        match e {
+            ApiError::Network { .. } | ApiError::InvalidUrl(_) => FailReason::MasterUnreachable,
+            ApiError::Timeout { .. } => FailReason::MasterTimeout,
+            ApiError::Http { .. } => FailReason::MasterHttpError,
+            ApiError::Decode(_) | ApiError::UnsupportedVersion => FailReason::MasterBadResponse,
+            ApiError::Empty => FailReason::MasterEmptyList,
+            ApiError::Server { .. } => rejected,
+        }
+    }
 }
 
 
 impl Application for LoaderMainInterface {
     type Executor = executor::Default;
     type Message = Message;
-    type Flags = ();
+    type Flags = LoaderFlags;
 
-    fn new(_flags: ()) -> (Self, Command<Self::Message>) {
+    fn new(flags: LoaderFlags) -> (Self, Command<Self::Message>) {
+        let profile = flags.game.profile();
+        let settings = flags.settings;
+        let mut server_list = ServerList::new();
+        server_list.import(settings.imported.clone());
+        let mut pending_selection = None;
+        if let Some(link) = &flags.link {
+            server_list.import(vec![link.to_server()]);
+            server_list.select_hostname(&link.hostname);
+        }
+        else if let Some(hostname) = &settings.selected_server {
+            if !server_list.select_hostname(hostname) {
+                pending_selection = Some(hostname.clone());
+            }
+        }
+        let split_pane = split::State::new(settings.window.divider, split::Axis::Vertical);
+        let mut loader = LoaderMainInterface{
+            api: flags.masters,
+            patch: Patches::new(profile),
+            known_keys: KnownKeys::load(),
+            settings,
+            pending_selection,
+            exiting: false,
+            cur_passwd: Zeroizing::default(),
+            passwords: None,
+            asking_passphrase: false,
+            passphrase: Zeroizing::default(),
+            pending_passwords: Vec::new(),
+            watcher: GameWatcher::new(),
+            detecting: false,
+            auto_patch: flags.auto_patch,
+            instances: Vec::new(),
+            instance_choice: None,
+            last_server: watch::load_last_server(profile),
+            launcher: flags.launcher,
+            launching: None,
+            status: None,
+
+            topbar: TopBar::new(),
+            server_list,
+            detail_panel: DetailPanel::new(),
+            split_pane,
+        };
+        loader.load_password();
+        let registered = loader.register_link_handler();
         (
-            LoaderMainInterface{
-                api: MasterServerApi::new(*crate::api::MASTER_SERVER_ADDR_DEF, 1).unwrap(),
-                patch: Patches::new(),
-                cur_passwd: String::new(),
-
-                topbar: TopBar::new(),
-                server_list: ServerList::new(),
-                detail_panel: DetailPanel::new(),
-                split_pane: split::State::new(None, split::Axis::Vertical),
-            },
-            Command::perform(async {}, |_| Message::ListMessage(ListMessage::UpdateServerList)) // ugly
+            loader,
+            Command::batch([
+                Command::perform(async {}, |_| Message::ListMessage(ListMessage::UpdateServerList)), // ugly
+                registered,
+            ])
         )
     }
 
     fn title(&self) -> String {
-        "Dark Souls III - Another Open Server Loader".to_string()
+        format!("{} - Another Open Server Loader", self.patch.profile().name)
     }
 
     fn update(
@@ -81,62 +245,247 @@ impl Application for LoaderMainInterface {
         message: Self::Message
     ) -> Command<Self::Message> {
         match message {
-            Message::Patch => {
-                if let Some(row) = self.server_list.find_selected_mut() {
-                    row.server.passwd = self.cur_passwd.clone();
-
-                    match self.patch.find_process() {
-                        Ok(pid) => {
-                            let api = self.api.clone();
-
-                            let ip_addr = row.server.ip_addr.clone();
-                            let mut pubkey = row.server.pubkey.clone();
-                            let hostname = row.server.hostname.clone();
-                            let passwd = row.server.passwd.clone();
-
-                            Command::perform(async move {
-                                    if pubkey.is_empty() {
-                                        pubkey = api
-                                            .get_pubkey(&ip_addr, &passwd)
-                                            .await
-                                            .map_err(|e| (FailReason::FetchPublicKeyFail, e.to_string()))?;
-                                    }
-                                    Patches::patch(pid, &hostname, &pubkey).map_err(|e| (FailReason::PatchFail, e.to_string())).map(|_| ())
-                                }, 
-                                |r| {
-                                    match r {
-                                        Ok(_) => {
-                                            Message::Nothing
-                                        },
-                                        Err(e) => {
-                                            Message::Fail(e.0, e.1)
-                                        }
-                                    }
+            Message::Patch => self.find_instances(InstanceAction::Patch),
+            Message::InstancesFound(action, found) => {
+                let instances = match found {
+                    Ok(instances) => instances,
+                    Err((reason, e)) => return self.update(Message::Fail(reason, e)),
+                };
+                match action {
+                    InstanceAction::Patch => self.fetch_pubkey(PatchTarget::Processes(instances)),
+                    InstanceAction::Restore => Command::perform(async move {
+                            instances
+                                .into_iter()
+                                .map(|(pid, version)| (pid, Patches::restore(pid, &version).map_err(|e| e.to_string())))
+                                .collect()
+                        },
+                        Message::Restored),
+                    InstanceAction::Inspect => {
+                        let profile = self.patch.profile();
+                        let servers: Vec<Server> = self.server_list.rows
+                            .iter()
+                            .filter(|row| profile.lists(&row.server))
+                            .map(|row| row.server.clone())
+                            .collect();
+                        // Reads the game executable when the official server info isn't known yet
+                        Command::perform(async move {
+                                instances
+                                    .iter()
+                                    .map(|(pid, version)| inspect::inspect(profile, *pid, version, &servers).map(|i| i.to_string()))
+                                    .collect::<Result<Vec<_>>>()
+                            },
+                            |found| match found {
+                                Ok(lines) => Message::Inspected(lines.join("\n")),
+                                Err(e) => Message::Fail(FailReason::InspectFail, e.to_string()),
+                            })
+                    },
+                }
+            },
+            
+            Message::PublicKeyFetched(target, hostname, pubkey) => {
+                if let Some(row) = self.server_list.rows.iter_mut().find(|row| row.server.hostname == hostname) {
+                    row.server.pubkey = pubkey.clone();
+                }
+
+                let fingerprint = match pubkey::fingerprint(&pubkey) {
+                    Ok(fingerprint) => fingerprint,
+                    Err(e) => return self.update(Message::Fail(FailReason::InvalidPublicKey, e.to_string())),
+                };
+                match self.known_keys.check(&hostname, &fingerprint) {
+                    Trust::Known => {},
+                    Trust::New => self.remember_key(&hostname, &fingerprint),
+                    Trust::Changed { previous } => {
+                        let text = format!("{}\n\n{}: {}\n{}: {}",
+                            TEXT_LOCALIZED_STRING[&TextType::KeyChangedWarning],
+                            TEXT_LOCALIZED_STRING[&TextType::PreviousKey], previous,
+                            TEXT_LOCALIZED_STRING[&TextType::CurrentKey], fingerprint
+                        );
+                        let confirmed = MessageDialog::new()
+                            .set_title("Warning")
+                            .set_type(MessageType::Warning)
+                            .set_text(&text)
+                            .show_confirm()
+                            .unwrap_or(false);
+                        if !confirmed {
+                            return Command::none();
+                        }
+                        self.remember_key(&hostname, &fingerprint);
+                    },
+                }
+
+                match target {
+                    PatchTarget::Processes(instances) => Command::perform(async move {
+                            instances
+                                .into_iter()
+                                .map(|(pid, version)| {
+                                    let report = Patches::patch(pid, &version, &hostname, &pubkey)
+                                        .and_then(|before| Patches::verify(pid, &version, &hostname, &pubkey, &before));
+                                    (pid, report.map_err(|e| e.to_string()))
                                 })
+                                .collect()
                         },
-                        Err(e) => {
-                            self.update(Message::Fail(FailReason::ProcessNotFound, e.to_string()))
+                        Message::Patched),
+                    PatchTarget::Launch => {
+                        match self.launcher.launch(self.patch.profile()) {
+                            Ok(launch) => {
+                                self.launching = Some((launch, ServerInfo { hostname, pubkey }));
+                                Command::none()
+                            },
+                            Err(e) => self.update(Message::Fail(FailReason::LaunchFail, e.to_string())),
                         }
+                    },
+                    PatchTarget::Exe(exe) => {
+                        let profile = self.patch.profile();
+                        Command::perform(async move {
+                            offline::patch_exe(profile, &exe, &offline::default_output(&exe), &hostname, &pubkey)
+                        },
+                        |r| {
+                            match r {
+                                Ok(manifest) => Message::ExePatched(manifest.output),
+                                Err(e) => Message::Fail(FailReason::PatchExeFail, e.to_string()),
+                            }
+                        })
+                    },
+                }
+            },
+            Message::WatchTick => {
+                if self.detecting {
+                    return Command::none();
+                }
+                if let Some((launch, _)) = &mut self.launching {
+                    match launch.step(&mut self.patch) {
+                        Ok(WatchStep::Detect(detection)) => return self.detect(Watching::Launch, detection),
+                        Ok(WatchStep::Done(_)) => {},
+                        Err(e) => {
+                            self.launching = None;
+                            return self.update(Message::Fail(FailReason::LaunchFail, e.to_string()));
+                        },
                     }
                 }
-                else {
-                    self.update(Message::Fail(FailReason::ListNoSelected, "No row is selected".into()))
+                if !self.auto_patch {
+                    return Command::none();
                 }
-            }, 
-            
+                match self.watcher.step(&mut self.patch) {
+                    WatchStep::Detect(detection) => self.detect(Watching::AutoPatch, detection),
+                    WatchStep::Done(event) => self.auto_patch(event),
+                }
+            },
+            Message::Detected(watching, detection, version) => {
+                self.detecting = false;
+                // The game was switched while detecting
+                if detection.profile().game != self.patch.profile().game {
+                    return Command::none();
+                }
+                match watching {
+                    Watching::Launch => {
+                        let event = match &mut self.launching {
+                            Some((launch, _)) => launch.detected(detection.pid, version),
+                            None => return Command::none(),
+                        };
+                        match event {
+                            WatchEvent::Ready(pid, version) => {
+                                let (_, info) = self.launching.take().unwrap();
+                                Command::perform(async move {
+                                        let report = Patches::patch(pid, &version, &info.hostname, &info.pubkey)
+                                            .and_then(|before| Patches::verify(pid, &version, &info.hostname, &info.pubkey, &before));
+                                        vec![(pid, report.map_err(|e| e.to_string()))]
+                                    },
+                                    Message::Patched)
+                            },
+                            _ => Command::none(),
+                        }
+                    },
+                    Watching::AutoPatch if self.auto_patch => {
+                        let event = self.watcher.detected(detection.pid, version);
+                        self.auto_patch(event)
+                    },
+                    Watching::AutoPatch => Command::none(),
+                }
+            },
+            Message::AutoPatched(pid, result) => {
+                let verified = matches!(&result, Ok(report) if report.result == Verification::Matches);
+                self.watcher.patched(pid, verified);
+                // No dialog when it worked, the user is busy starting the game
+                match result {
+                    Ok(VerifyReport { result: Verification::Matches, found }) => {
+                        let hostname = found.map_or_else(|| "?".into(), |info| info.hostname);
+                        self.status = Some(format!("{} {}", TEXT_LOCALIZED_STRING[&TextType::AutoPatched], hostname));
+                        Command::none()
+                    },
+                    Ok(report) => self.update(Message::Fail(FailReason::PatchVerifyFail, format!("{:?}", report.result))),
+                    Err(e) => self.update(Message::Fail(FailReason::PatchFail, e)),
+                }
+            },
+            Message::Inspected(text) => {
+                self.show_info(&text);
+                Command::none()
+            },
+            Message::ExePatched(output) => {
+                self.show_info(&format!("{}\n{}", TEXT_LOCALIZED_STRING[&TextType::ExePatched], output.to_string_lossy()));
+                Command::none()
+            },
+            Message::Restored(results) => {
+                match describe_failures(&results) {
+                    None => {
+                        self.show_info(TEXT_LOCALIZED_STRING[&TextType::RestoreSucceeded]);
+                        Command::none()
+                    },
+                    Some(problems) => self.update(Message::Fail(FailReason::RestoreFail, problems)),
+                }
+            },
+            Message::Patched(results) => {
+                match describe_failures(&results) {
+                    None => {
+                        let found = results
+                            .into_iter()
+                            .find_map(|(_, r)| r.ok().and_then(|report| report.found));
+                        let hostname = found.as_ref().map_or_else(|| "?".into(), |info| info.hostname.clone());
+                        if let Some(info) = found {
+                            if let Err(e) = watch::save_last_server(self.patch.profile(), &info) {
+                                self.status = Some(format!("Can't remember the server: {}", e));
+                            }
+                            self.last_server = Some(info);
+                        }
+                        self.show_info(&format!("{} {}", TEXT_LOCALIZED_STRING[&TextType::PatchSucceeded], hostname));
+                        Command::none()
+                    },
+                    Some(problems) => {
+                        let reason = if results.iter().any(|(_, r)| r.is_err()) { FailReason::PatchFail } else { FailReason::PatchVerifyFail };
+                        self.update(Message::Fail(reason, problems))
+                    },
+                }
+            },
+
             Message::ListMessage(m) => {
+                let mut selected = false;
                 if let ListMessage::RowMessage(id, RowMessage::ToggleSelection) = m {
                     if let Some(row) = self.server_list.find_by_id(id) {
-                        self.cur_passwd = row.server.passwd.clone();
+                        self.settings.selected_server = Some(row.server.hostname.clone());
+                        self.pending_selection = None;
+                        selected = true;
+                    }
+                }
+                let listed = matches!(m, ListMessage::UpdateServerListComplete(_));
+                let command = self.server_list.update(m, &self.api);
+                if listed {
+                    if let Some(hostname) = self.pending_selection.take() {
+                        selected = self.server_list.select_hostname(&hostname);
                     }
                 }
-                self.server_list.update(m, &self.api).map(map_list_message)
+                if selected {
+                    self.load_password();
+                }
+                command.map(map_list_message)
             },
             Message::TopBarMessage(m) => {
                 match m {
                     TopBarMessage::ChooseConfigFile => {
                         let mes = match choose_config_file() {
                             Ok(servers) => {
+                                // Passwords that came with the configs stay in the list for this session,
+                                // they're only kept when "remember password" is ticked
+                                self.settings.add_imported(&servers);
+                                self.save_settings();
                                 Message::ListMessage(ListMessage::ImportConfig(servers))
                             },
                             Err(e) => {
@@ -145,6 +494,45 @@ impl Application for LoaderMainInterface {
                         };
                         self.update(mes)
                     },
+                    TopBarMessage::Restore => self.find_instances(InstanceAction::Restore),
+                    TopBarMessage::Launch => {
+                        if self.launching.is_some() {
+                            return Command::none();
+                        }
+                        self.fetch_pubkey(PatchTarget::Launch)
+                    },
+                    TopBarMessage::Inspect => self.find_instances(InstanceAction::Inspect),
+                    TopBarMessage::ChooseInstance(choice) => {
+                        self.instance_choice = Some(choice);
+                        Command::none()
+                    },
+                    TopBarMessage::ChooseGame(game) => {
+                        if game != self.patch.profile().game {
+                            let profile = game.profile();
+                            self.patch = Patches::new(profile);
+                            self.watcher = GameWatcher::new();
+                            self.instances.clear();
+                            self.instance_choice = None;
+                            self.last_server = watch::load_last_server(profile);
+                            // The selected server may belong to the other game
+                            self.server_list.selected = 0;
+                            self.pending_selection = None;
+                            self.cur_passwd = Zeroizing::default();
+                        }
+                        Command::none()
+                    },
+                    TopBarMessage::AutoPatch(on) => {
+                        self.auto_patch = on;
+                        self.status = None;
+                        Command::none()
+                    },
+                    TopBarMessage::PatchExe => {
+                        match choose_game_exe(self.patch.profile()) {
+                            Ok(Some(exe)) => self.fetch_pubkey(PatchTarget::Exe(exe)),
+                            Ok(None) => Command::none(),
+                            Err(e) => self.update(Message::Fail(FailReason::ChooseFileFail, e.to_string())),
+                        }
+                    },
                     _ => {
                         self.topbar.update(m).map(map_topbar_message)
                     }
@@ -152,54 +540,360 @@ impl Application for LoaderMainInterface {
             },
             Message::Fail(reason, description) => {
                 let text = format!("{}\nDetail: {}", FAIL_REASON_LOCALIZED_STRING[&reason], &description);
-                if let Err(e) = MessageDialog::new()
+                if MessageDialog::new()
                     .set_title("Error")
                     //.set_type(MessageType::Error)
                     .set_text(&text)
                     .show_alert()
+                    .is_err()
                     {
-                        println!("Error: {}", e);
+                        self.status = Some(text.replace('\n', " "));
                     }
                 Command::none()
             },
             Message::OnResize(pos) => { 
                 self.split_pane.set_divider_position(pos);
+                self.settings.window.divider = Some(pos);
+                Command::none()
+            },
+            Message::WindowResized(width, height) => {
+                self.settings.window.width = width;
+                self.settings.window.height = height;
+                Command::none()
+            },
+            Message::CloseRequested => {
+                self.save_settings();
+                self.exiting = true;
                 Command::none()
             },
             Message::PasswordInput(s) => {
-                self.cur_passwd = s;
+                self.cur_passwd = Zeroizing::new(s);
                 Command::none()
+            },
+            Message::RememberPassword(remember) => {
+                let hostname = match self.server_list.find_selected_mut() {
+                    Some(row) => row.server.hostname.clone(),
+                    None => return Command::none(),
+                };
+                self.settings.set_remember_password(&hostname, remember);
+                self.save_settings();
+                if remember && self.cur_passwd.is_empty() {
+                    // Saved once it's typed and used
+                    return Command::none();
+                }
+                let password = if remember { Some(self.cur_passwd.clone()) } else { None };
+                self.store_password(hostname, password)
+            },
+            Message::PassphraseInput(s) => {
+                self.passphrase = Zeroizing::new(s);
+                Command::none()
+            },
+            Message::UnlockPasswords => {
+                let result = self.passwords.get_or_insert_with(PasswordStore::open).unlock(&self.passphrase);
+                self.passphrase = Zeroizing::default();
+                if let Err(e) = result {
+                    return self.update(Message::Fail(FailReason::PasswordStoreFail, e.to_string()));
+                }
+                self.asking_passphrase = false;
+                let pending = std::mem::take(&mut self.pending_passwords);
+                let commands: Vec<_> = pending
+                    .into_iter()
+                    .map(|(hostname, password)| self.store_password(hostname, password))
+                    .collect();
+                self.recall_password();
+                Command::batch(commands)
             }
-            Message::Nothing => { Command::none() },
         }
     }
     
+    fn subscription(&self) -> Subscription<Self::Message> {
+        let window_events = iced_native::subscription::events_with(|event, _| match event {
+            Event::Window(window::Event::Resized { width, height }) => Some(Message::WindowResized(width, height)),
+            Event::Window(window::Event::CloseRequested) => Some(Message::CloseRequested),
+            _ => None,
+        });
+        if self.auto_patch || self.launching.is_some() {
+            Subscription::batch([window_events, iced::time::every(WATCH_INTERVAL).map(|_| Message::WatchTick)])
+        }
+        else {
+            window_events
+        }
+    }
+
+    fn should_exit(&self) -> bool {
+        self.exiting
+    }
+
     fn view(&mut self) -> iced::Element<'_, Self::Message> {
+        let profile = self.patch.profile();
         let topbar = self.topbar
-            .view()
+            .view(profile.game, self.auto_patch, &self.instances, self.instance_choice.clone())
             .map(map_topbar_message);
         let mut col = Column::new()
             .push(topbar);
+        if let Some(status) = &self.status {
+            col = col.push(Container::new(Text::new(status.as_str()).size(16)).padding(5));
+        }
 
-        let heads = ["Name", "Address", "Player Count"];
+        let heads = ["Name", "Address", "Player Count", "Ping", "Status"];
 
-        if let Some(row) = self.server_list.rows.iter().filter(|row| row.id == self.server_list.selected).next() {
-            let detail_panel = self.detail_panel.view(row.server.clone(), &self.cur_passwd);
+        if let Some(row) = self.server_list.rows.iter().find(|row| row.id == self.server_list.selected) {
+            let known_fingerprint = self.known_keys.get(&row.server.hostname);
+            let remember = self.settings.remembers_password(&row.server.hostname);
+            let new_file = self.passwords.as_ref().is_some_and(PasswordStore::is_new);
+            let passphrase = if self.asking_passphrase { Some((self.passphrase.as_str(), new_file)) } else { None };
+            let detail_panel = self.detail_panel.view(row.server.clone(), &self.cur_passwd, known_fingerprint, remember, passphrase);
             let split = Split::new(
                 &mut self.split_pane, 
-                self.server_list.view(heads).map(map_list_message),
+                self.server_list.view(heads, profile).map(map_list_message),
                 detail_panel,
                 Message::OnResize
             );
             col = col.push(split);
         }
         else {
-            col = col.push(self.server_list.view(heads).map(map_list_message));
+            col = col.push(self.server_list.view(heads, profile).map(map_list_message));
         }
         
         col.into()
     }
 }
+impl LoaderMainInterface {
+    // The game instances to act on, asking the user to pick when several run.
+    // Their versions are detected in the background, then `action` is taken.
+    fn find_instances(&mut self, action: InstanceAction) -> Command<Message> {
+        self.instances = self.patch.find_processes();
+        let pids: Vec<u32> = match (&self.instance_choice, self.instances.as_slice()) {
+            (_, []) => return self.update(Message::Fail(FailReason::ProcessNotFound, "Can't find process".into())),
+            (_, [only]) => vec![only.pid],
+            (Some(InstanceChoice::All), all) => all.iter().map(|p| p.pid).collect(),
+            (Some(InstanceChoice::One(chosen)), all) if all.iter().any(|p| p.pid == chosen.pid) => vec![chosen.pid],
+            (_, all) => {
+                self.instance_choice = None;
+                let e = format!("{} game instances are running", all.len());
+                return self.update(Message::Fail(FailReason::ChooseInstance, e));
+            },
+        };
+        let detections: Vec<Detection> = pids.into_iter().map(|pid| self.patch.detection(pid)).collect();
+        Command::perform(async move {
+                detections
+                    .iter()
+                    .map(|detection| {
+                        detection.run()
+                            .map(|version| (detection.pid, version))
+                            .map_err(|e| (FailReason::UnknownGameVersion, format!("Game {}: {}", detection.pid, e)))
+                    })
+                    .collect()
+            },
+            move |found| Message::InstancesFound(action, found))
+    }
+
+    // Find out whether the game the watcher or launch follows is ready, without blocking the UI
+    fn detect(&mut self, watching: Watching, detection: Detection) -> Command<Message> {
+        self.detecting = true;
+        Command::perform(async move {
+                let version = detection.ready();
+                (detection, version)
+            },
+            move |(detection, version)| Message::Detected(watching, detection, version))
+    }
+
+    // Patch the game the watcher found to the last server
+    fn auto_patch(&mut self, event: WatchEvent) -> Command<Message> {
+        match (event, self.last_server.clone()) {
+            (WatchEvent::NotRunning | WatchEvent::Starting(_), _) => {
+                self.status = Some(TEXT_LOCALIZED_STRING[&TextType::WaitingForGame].to_string());
+                Command::none()
+            },
+            (WatchEvent::Ready(..), None) => {
+                self.status = Some(TEXT_LOCALIZED_STRING[&TextType::NoServerToAutoPatch].to_string());
+                Command::none()
+            },
+            (WatchEvent::Ready(pid, version), Some(info)) => Command::perform(async move {
+                    Patches::patch(pid, &version, &info.hostname, &info.pubkey)
+                        .and_then(|before| Patches::verify(pid, &version, &info.hostname, &info.pubkey, &before))
+                        .map_err(|e| e.to_string())
+                },
+                move |r| Message::AutoPatched(pid, r)),
+            _ => Command::none(),
+        }
+    }
+
+    // Get the selected server's key, from the master if the list doesn't have it yet
+    fn fetch_pubkey(&mut self, target: PatchTarget) -> Command<Message> {
+        let row = match self.server_list.find_selected_mut() {
+            Some(row) => row,
+            None => return self.update(Message::Fail(FailReason::ListNoSelected, "No row is selected".into())),
+        };
+        let api = self.api.clone();
+        let mut server = row.server.clone();
+        // The password goes along on its own and is wiped once the request is done, never kept in the list
+        server.passwd.zeroize();
+        let mut pubkey = row.server.pubkey.clone();
+        let hostname = row.server.hostname.clone();
+        let passwd = self.cur_passwd.clone();
+
+        let stored = if self.settings.remembers_password(&hostname) && !passwd.is_empty() {
+            self.store_password(hostname.clone(), Some(passwd.clone()))
+        }
+        else {
+            Command::none()
+        };
+
+        let fetched = Command::perform(async move {
+                if pubkey.is_empty() {
+                    pubkey = api
+                        .get_pubkey(&server, &passwd)
+                        .await
+                        .map_err(|e| (FailReason::from_api_error(&e, FailReason::FetchPublicKeyFail), e.to_string()))?;
+                }
+                Ok((hostname, pubkey))
+            },
+            move |r: Result<(String, String), (FailReason, String)>| {
+                match r {
+                    Ok((hostname, pubkey)) => {
+                        Message::PublicKeyFetched(target.clone(), hostname, pubkey)
+                    },
+                    Err(e) => {
+                        Message::Fail(e.0, e.1)
+                    }
+                }
+            });
+        Command::batch([stored, fetched])
+    }
+
+    // Show the selected server's password, from the password store when it was remembered
+    fn load_password(&mut self) {
+        self.cur_passwd = match self.server_list.find_selected_mut() {
+            Some(row) => Zeroizing::new(row.server.passwd.clone()),
+            None => Zeroizing::default(),
+        };
+        self.recall_password();
+    }
+
+    fn recall_password(&mut self) {
+        let hostname = match self.server_list.find_selected_mut() {
+            Some(row) if self.cur_passwd.is_empty() && self.settings.remembers_password(&row.server.hostname) => row.server.hostname.clone(),
+            _ => return,
+        };
+        match self.passwords.get_or_insert_with(PasswordStore::open).get(&hostname) {
+            Ok(Some(password)) => self.cur_passwd = password,
+            Ok(None) => {},
+            Err(PasswordError::Locked) => self.asking_passphrase = true,
+            Err(e) => self.status = Some(format!("Can't read the saved password of '{}': {}", hostname, e)),
+        }
+    }
+
+    // Save `password` for `hostname`, or forget it when None
+    fn store_password(&mut self, hostname: String, password: Option<Zeroizing<String>>) -> Command<Message> {
+        let store = self.passwords.get_or_insert_with(PasswordStore::open);
+        let result = match &password {
+            Some(password) => store.set(&hostname, password),
+            None => store.delete(&hostname),
+        };
+        match result {
+            Ok(()) => Command::none(),
+            Err(PasswordError::Locked) => {
+                self.pending_passwords.retain(|(host, _)| !host.eq_ignore_ascii_case(&hostname));
+                self.pending_passwords.push((hostname, password));
+                self.asking_passphrase = true;
+                Command::none()
+            },
+            Err(e) => self.update(Message::Fail(FailReason::PasswordStoreFail, e.to_string())),
+        }
+    }
+
+    fn remember_key(&mut self, hostname: &str, fingerprint: &str) {
+        if let Err(e) = self.known_keys.remember(hostname, fingerprint) {
+            self.status = Some(format!("Can't save known keys: {}", e));
+        }
+    }
+
+    // Ask once whether ds3os:// links should open with the loader, then keep them pointing at this executable
+    fn register_link_handler(&mut self) -> Command<Message> {
+        if !cfg!(target_os = "linux") {
+            return Command::none();
+        }
+        let wanted = match self.settings.link_handler {
+            Some(wanted) => wanted,
+            None => {
+                let answer = MessageDialog::new()
+                    .set_title("DS3OS Loader")
+                    .set_type(MessageType::Info)
+                    .set_text(TEXT_LOCALIZED_STRING[&TextType::RegisterLinkHandler])
+                    .show_confirm();
+                let wanted = match answer {
+                    Ok(wanted) => wanted,
+                    // Ask again next time when no dialog could be shown
+                    Err(_) => return Command::none(),
+                };
+                self.settings.link_handler = Some(wanted);
+                self.save_settings();
+                wanted
+            },
+        };
+        if !wanted {
+            return Command::none();
+        }
+        match deeplink::register() {
+            Ok(()) => Command::none(),
+            Err(e) => {
+                let e = e.to_string();
+                Command::perform(async {}, move |_| Message::Fail(FailReason::LinkHandlerFail, e.clone()))
+            },
+        }
+    }
+
+    fn show_info(&mut self, text: &str) {
+        if MessageDialog::new()
+            .set_title("Done")
+            .set_type(MessageType::Info)
+            .set_text(text)
+            .show_alert()
+            .is_err()
+        {
+            // Still tell, if only in the status line
+            self.status = Some(text.replace('\n', " "));
+        }
+    }
+
+    fn save_settings(&mut self) {
+        // The game shown when leaving is the one to come back to
+        self.settings.game = self.patch.profile().game;
+        if let Err(e) = self.settings.save() {
+            self.status = Some(format!("Can't save settings: {}", e));
+        }
+    }
+}
+
+// One line per instance that didn't end up as intended, None when they all did
+fn describe_failures(results: &InstanceResults) -> Option<String> {
+    let problems: Vec<String> = results
+        .iter()
+        .filter_map(|(pid, result)| {
+            let report = match result {
+                Ok(report) => report,
+                Err(e) => return Some(format!("Game {}: {}", pid, e)),
+            };
+            let found = report.found.as_ref().map_or("?", |info| info.hostname.as_str());
+            match report.result {
+                Verification::Matches => None,
+                Verification::Partial { first_diff } | Verification::Mismatch { first_diff } => Some(format!(
+                    "Game {}: {:?}, first differing byte at offset {}, game now points at '{}'", pid, report.result, first_diff, found
+                )),
+            }
+        })
+        .collect();
+    if problems.is_empty() { None } else { Some(problems.join("\n")) }
+}
+
+fn choose_game_exe(profile: &GameProfile) -> Result<Option<PathBuf>> {
+    Ok(FileDialog::new()
+        .add_filter(&format!("{} ({})", profile.name, profile.exe_name), &["exe"])
+        .set_location("~/")
+        .show_open_single_file()?)
+}
+
 fn choose_config_file() -> Result<Vec<Server>> {
     Ok(FileDialog::new()
         .add_filter("Server Config File (*.ds3osconfig)", &["ds3osconfig"])
@@ -208,12 +902,10 @@ fn choose_config_file() -> Result<Vec<Server>> {
         .show_open_multiple_file()?
         .into_iter()
         .filter_map(|path| {
-            match File::open(path.clone()) {
-                Ok(file) => {
-                    serde_json::from_reader::<_, Server>(BufReader::new(file)).ok()
-                },
+            match imported::read_config(&path) {
+                Ok(server) => Some(server),
                 Err(e) => {
-                    println!("Import file '{}' failed! Reason: {}", path.to_string_lossy(), e.to_string());
+                    println!("Import file '{}' failed! Reason: {}", path.to_string_lossy(), e);
                     None
                 }
             }
@@ -248,9 +940,9 @@ pub enum Icon {
     PlayDark,
 }
 
-impl Into<String> for Icon {
-    fn into(self) -> String {
-        match self {
+impl From<Icon> for String {
+    fn from(val: Icon) -> Self {
+        match val {
             Icon::Refresh => "\u{E800}".into(),
             Icon::TrashBinLight => "\u{E801}".into(),
             Icon::TrashBinDark => "\u{E802}".into(),
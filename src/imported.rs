@@ -0,0 +1,15 @@
+// Servers imported from .ds3osconfig files, the list itself is kept in the settings.
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+
+use crate::api::Server;
+
+/// Read a server's .ds3osconfig file.
+pub fn read_config(path: &Path) -> Result<Server> {
+    let file = File::open(path).map_err(|e| anyhow!("Can't open '{}': {}", path.to_string_lossy(), e))?;
+    serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| anyhow!("'{}' isn't a server config: {}", path.to_string_lossy(), e))
+}
@@ -0,0 +1,115 @@
+// Telling which server a running game points at.
+use std::fmt;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::api::Server;
+use crate::patch::{Patches, GameVersion, ServerInfo};
+use crate::offline;
+use crate::profile::GameProfile;
+use crate::localize::{TEXT_LOCALIZED_STRING, TextType};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Identity {
+    // Name of the matching server, and whether its key is the one the game holds.
+    // None when the list doesn't have the server's key.
+    Listed { name: String, key_matches: Option<bool> },
+    Official,
+    Unknown,
+}
+
+/// What a game instance holds and where that comes from.
+#[derive(Debug, Clone, Serialize)]
+pub struct Inspection {
+    pub pid: u32,
+    pub info: ServerInfo,
+    pub identity: Identity,
+}
+
+pub fn inspect(profile: &'static GameProfile, pid: u32, version: &GameVersion, servers: &[Server]) -> Result<Inspection> {
+    let info = Patches::read_server_info(pid, version)?;
+    // Without a snapshot, the executable still holds the official server info
+    let official = Patches::original_server_info(pid, version).or_else(|| {
+        let exe = Patches::new(profile).exe_of(pid)?;
+        offline::original_server_info(profile, &exe).ok()
+    });
+    let identity = identify(&info, servers, official.as_ref());
+    Ok(Inspection { pid, info, identity })
+}
+
+/// Match `info` against the server list first, then the official server info if we know it.
+pub fn identify(info: &ServerInfo, servers: &[Server], official: Option<&ServerInfo>) -> Identity {
+    let listed = servers.iter().filter(|s| s.hostname.eq_ignore_ascii_case(&info.hostname));
+    // Prefer the sure match, then a server whose key isn't known over one with a different key
+    let rank = |key_matches: Option<bool>| match key_matches {
+        Some(true) => 2,
+        None => 1,
+        Some(false) => 0,
+    };
+    let mut best: Option<(String, Option<bool>)> = None;
+    for server in listed {
+        let key_matches = match server.pubkey.trim() {
+            "" => None,
+            key => Some(key == info.pubkey.trim()),
+        };
+        if best.as_ref().is_none_or(|(_, best)| rank(key_matches) > rank(*best)) {
+            best = Some((server.name.clone(), key_matches));
+        }
+    }
+    if let Some((name, key_matches)) = best {
+        return Identity::Listed { name, key_matches };
+    }
+
+    if official.is_some_and(|official| official == info) {
+        Identity::Official
+    }
+    else {
+        Identity::Unknown
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {} '{}' (", TEXT_LOCALIZED_STRING[&TextType::GameInstance], self.pid,
+            TEXT_LOCALIZED_STRING[&TextType::CurrentlyPatchedTo], self.info.hostname)?;
+        match &self.identity {
+            Identity::Listed { name, key_matches: Some(true) } => write!(f, "{} '{}'", TEXT_LOCALIZED_STRING[&TextType::MatchesListedServer], name)?,
+            Identity::Listed { name, key_matches: Some(false) } => write!(f, "{} '{}'", TEXT_LOCALIZED_STRING[&TextType::ListedServerKeyDiffers], name)?,
+            Identity::Listed { name, key_matches: None } => write!(f, "{} '{}'", TEXT_LOCALIZED_STRING[&TextType::ListedServerKeyUnknown], name)?,
+            Identity::Official => write!(f, "{}", TEXT_LOCALIZED_STRING[&TextType::OfficialServer])?,
+            Identity::Unknown => write!(f, "{}", TEXT_LOCALIZED_STRING[&TextType::UnknownServer])?,
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(hostname: &str, pubkey: &str) -> ServerInfo {
+        ServerInfo { hostname: hostname.into(), pubkey: pubkey.into() }
+    }
+
+    fn server(name: &str, hostname: &str, pubkey: &str) -> Server {
+        serde_json::from_value(serde_json::json!({ "Name": name, "Hostname": hostname, "PublicKey": pubkey })).unwrap()
+    }
+
+    #[test]
+    fn identifies_by_hostname_and_key() {
+        let held = info("ds3.example.com", "KEY");
+        let listed = |key_matches| Identity::Listed { name: "Listed".into(), key_matches };
+
+        // A listed server whose key isn't known doesn't vouch for the one the game holds
+        assert_eq!(identify(&held, &[server("Listed", "DS3.example.com", " ")], None), listed(None));
+        assert_eq!(identify(&held, &[server("Listed", "ds3.example.com", "OTHER")], None), listed(Some(false)));
+        let servers = [server("Other", "ds3.example.com", "OTHER"), server("Listed", "ds3.example.com", "KEY\n")];
+        assert_eq!(identify(&held, &servers, None), listed(Some(true)));
+
+        assert_eq!(identify(&held, &[], Some(&info("ds3.example.com", "KEY"))), Identity::Official);
+        assert_eq!(identify(&held, &[], Some(&info("ds3.example.com", "OTHER"))), Identity::Unknown);
+        assert_eq!(identify(&held, &[], None), Identity::Unknown);
+    }
+}
@@ -0,0 +1,154 @@
+// Starting the game ourselves, so it can be patched as soon as it's up.
+use std::process::{Child, Command};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow};
+
+use crate::patch::{Patches, GameVersion};
+use crate::profile::GameProfile;
+use crate::watch::{GameWatcher, WatchEvent, WatchStep};
+
+// Steam may have to update or sync the game before it starts
+const LAUNCH_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How the game gets started.
+#[derive(Debug, Clone)]
+pub enum Launcher {
+    // Ask the Steam client to run the game
+    Steam,
+    // Run a command line
+    Command { program: String, args: Vec<String> },
+}
+
+impl Launcher {
+    /// Run a command line, split into words the way a POSIX shell would, so paths with spaces
+    /// can be quoted. When it calls a `proton` script directly, the environment Steam would give it is filled in.
+    pub fn from_command(line: &str) -> Result<Self> {
+        let mut words = shell_words::split(line)
+            .map_err(|e| anyhow!("Can't read launch command '{}': {}", line, e))?
+            .into_iter();
+        let program = words.next().ok_or_else(|| anyhow!("Launch command is empty"))?;
+        Ok(Launcher::Command { program, args: words.collect() })
+    }
+
+    fn command(&self, profile: &GameProfile) -> Result<Command> {
+        match self {
+            Launcher::Steam => {
+                let url = format!("steam://run/{}", profile.steam_app_id);
+                let mut command = Command::new(if cfg!(windows) { "cmd" } else { "steam" });
+                if cfg!(windows) {
+                    command.args(["/C", "start", ""]);
+                }
+                command.arg(url);
+                Ok(command)
+            },
+            Launcher::Command { program, args } => {
+                let mut command = Command::new(program);
+                command.args(args);
+                if program.ends_with("proton") {
+                    let steam = dirs::home_dir()
+                        .map(|home| home.join(".steam").join("steam"))
+                        .ok_or_else(|| anyhow!("No home directory to find Steam in"))?;
+                    let app_id = profile.steam_app_id.to_string();
+                    let compat_data = steam.join("steamapps").join("compatdata").join(&app_id);
+                    default_env(&mut command, "STEAM_COMPAT_CLIENT_INSTALL_PATH", &steam.to_string_lossy());
+                    default_env(&mut command, "STEAM_COMPAT_DATA_PATH", &compat_data.to_string_lossy());
+                    default_env(&mut command, "SteamAppId", &app_id);
+                }
+                Ok(command)
+            },
+        }
+    }
+
+    pub fn launch(&self, profile: &GameProfile) -> Result<Launch> {
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut command = self.command(profile)?;
+        let program = command.get_program().to_string_lossy().to_string();
+        let child = command
+            .spawn()
+            .map_err(|e| anyhow!("Can't run '{}': {}", program, e))?;
+
+        Ok(Launch {
+            child,
+            program,
+            started_at,
+            deadline: Instant::now() + LAUNCH_TIMEOUT,
+            watcher: GameWatcher::new(),
+        })
+    }
+}
+
+fn default_env(command: &mut Command, key: &str, value: &str) {
+    if std::env::var_os(key).is_none() {
+        command.env(key, value);
+    }
+}
+
+/// A game being started, until it's ready to be patched.
+pub struct Launch {
+    child: Child,
+    program: String,
+    // Seconds since the epoch
+    started_at: u64,
+    deadline: Instant,
+    watcher: GameWatcher,
+}
+
+impl Launch {
+    /// What was run, with its pid. With Steam that's the client, not the game.
+    pub fn describe(&self) -> String {
+        format!("'{}' ({})", self.program, self.child.id())
+    }
+
+    /// Look for the game among what we spawned. `steam://` hands the start over to a running
+    /// Steam client though, so any instance started after the launch counts as well.
+    pub fn poll(&mut self, patches: &mut Patches) -> Result<WatchEvent> {
+        match self.step(patches)? {
+            WatchStep::Done(event) => Ok(event),
+            WatchStep::Detect(detection) => Ok(self.detected(detection.pid, detection.ready())),
+        }
+    }
+
+    /// Like `poll`, leaving the detection of the game to the caller, see `GameWatcher::step_with`.
+    pub fn step(&mut self, patches: &mut Patches) -> Result<WatchStep> {
+        // Reap the launcher once it exits, Steam's usually does right away
+        let _ = self.child.try_wait();
+
+        let root = self.child.id();
+        let started_at = self.started_at;
+        let step = self.watcher.step_with(patches, |patches, found| {
+            found.iter()
+                .find(|p| p.pid == root || patches.is_descendant(p.pid, root))
+                .or_else(|| found.iter().find(|p| p.start_time >= started_at))
+                .map(|p| p.pid)
+        });
+
+        if matches!(step, WatchStep::Done(_)) && Instant::now() > self.deadline {
+            return Err(anyhow!("The game didn't start within {} minutes", LAUNCH_TIMEOUT.as_secs() / 60));
+        }
+        Ok(step)
+    }
+
+    pub fn detected(&mut self, pid: u32, version: Option<GameVersion>) -> WatchEvent {
+        self.watcher.detected(pid, version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_quoting() {
+        let launcher = Launcher::from_command(r#""/games/Proton 8.0/proton" run 'C:\Game Dir\DarkSoulsIII.exe'"#).unwrap();
+        match launcher {
+            Launcher::Command { program, args } => {
+                assert_eq!(program, "/games/Proton 8.0/proton");
+                assert_eq!(args, ["run", r"C:\Game Dir\DarkSoulsIII.exe"]);
+            },
+            Launcher::Steam => unreachable!(),
+        }
+        assert!(Launcher::from_command("  ").is_err());
+        assert!(Launcher::from_command("\"unclosed").is_err());
+    }
+}
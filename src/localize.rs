@@ -1,12 +1,14 @@
-use std::{collections::HashMap, hash::Hash};
+use std::{collections::HashMap, hash::Hash, ops::Index, sync::RwLock};
 
 use crate::gui::FailReason;
 
 use lazy_static::lazy_static;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use sys_locale::get_locale;
 
-#[derive(Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Language {
     Auto,
     English,
@@ -19,17 +21,47 @@ lazy_static! {
             (FailReason::ChooseFileFail, "Invalid file choosen!"),
             (FailReason::RefreshListFail, "Can't refresh the server list!"),
             (FailReason::PatchFail, "Exception happened during the patch"),
+            (FailReason::InvalidPublicKey, "The server's public key is invalid, the game would refuse it."),
+            (FailReason::UnknownGameVersion, "This version of the game isn't supported, patching it could crash the game."),
+            (FailReason::PatchVerifyFail, "The game's memory doesn't hold what was written, please restart the game and try again."),
+            (FailReason::RestoreFail, "Can't restore the official server, restarting the game will."),
+            (FailReason::PatchExeFail, "Can't write a patched copy of the game executable."),
+            (FailReason::ChooseInstance, "Several games are running, choose which one to use in the top bar."),
+            (FailReason::InspectFail, "Can't read the server info from the game."),
+            (FailReason::LaunchFail, "Can't start the game, check the launch command."),
+            (FailReason::PasswordStoreFail, "Can't access the saved passwords!"),
+            (FailReason::LinkHandlerFail, "Can't make ds3os:// links open with the loader!"),
             (FailReason::ListNoSelected, "Please select a server first!"),
             (FailReason::ProcessNotFound, "Game process not found, maybe you need open the game first."),
             (FailReason::FetchPublicKeyFail, "Can't fetch public key from the master server, most likely due to the incorrect password"),
+            (FailReason::MasterUnreachable, "Can't connect to the master server, please check your network connection."),
+            (FailReason::MasterTimeout, "The master server took too long to answer, please try again later."),
+            (FailReason::MasterHttpError, "The master server returned an error, it may be down for maintenance."),
+            (FailReason::MasterBadResponse, "The master server's response can't be understood, maybe the loader is outdated."),
+            (FailReason::MasterEmptyList, "The master server has no server online right now."),
         ])),
         (Language::SChinese, HashMap::from([
             (FailReason::ChooseFileFail, "无效的配置文件！"),
             (FailReason::RefreshListFail, "无法刷新服务器列表！"),
             (FailReason::PatchFail, "修改内存过程中发生错误"),
+            (FailReason::InvalidPublicKey, "服务器的公钥无效，游戏将无法使用它。"),
+            (FailReason::UnknownGameVersion, "不支持此版本的游戏，修改内存可能导致游戏崩溃。"),
+            (FailReason::PatchVerifyFail, "游戏内存中的数据与写入的不一致，请重启游戏后再试。"),
+            (FailReason::RestoreFail, "无法恢复官方服务器，重启游戏即可恢复。"),
+            (FailReason::PatchExeFail, "无法写入修改后的游戏程序副本。"),
+            (FailReason::ChooseInstance, "有多个游戏正在运行，请在顶栏中选择要使用的游戏。"),
+            (FailReason::InspectFail, "无法从游戏中读取服务器信息。"),
+            (FailReason::LaunchFail, "无法启动游戏，请检查启动命令。"),
+            (FailReason::PasswordStoreFail, "无法访问已保存的密码！"),
+            (FailReason::LinkHandlerFail, "无法使用加载器打开 ds3os:// 链接！"),
             (FailReason::ListNoSelected, "请先选择一个服务器"),
             (FailReason::ProcessNotFound, "未找到游戏进程，也许你应该先打开游戏。"),
             (FailReason::FetchPublicKeyFail, "从主服务器获取公钥失败，一般是由于密码错误"),
+            (FailReason::MasterUnreachable, "无法连接到主服务器，请检查网络连接。"),
+            (FailReason::MasterTimeout, "主服务器响应超时，请稍后再试。"),
+            (FailReason::MasterHttpError, "主服务器返回了错误，可能正在维护。"),
+            (FailReason::MasterBadResponse, "无法解析主服务器的响应，也许加载器需要更新。"),
+            (FailReason::MasterEmptyList, "主服务器上目前没有在线的服务器。"),
         ])),
     ]);
 
@@ -37,17 +69,104 @@ lazy_static! {
         (Language::English, HashMap::from([
             (TextType::PasswordRequired, "Need password"),
             (TextType::PasswordNotRequired, "No password"),
+            (TextType::StaleServerList, "Master server unreachable, showing the cached list from"),
+            (TextType::Probing, "Probing..."),
+            (TextType::Reachable, "Online"),
+            (TextType::Unreachable, "Unreachable"),
+            (TextType::KeyChangedWarning, "WARNING: this server's public key has changed since you last connected! Someone may be impersonating it. Only continue if the server owner told you the key changed."),
+            (TextType::PreviousKey, "Previous key"),
+            (TextType::CurrentKey, "Current key"),
+            (TextType::InvalidKey, "Invalid public key"),
+            (TextType::PatchSucceeded, "Patched successfully, the game now connects to"),
+            (TextType::Restore, "Restore"),
+            (TextType::RestoreSucceeded, "The game connects to the official server again."),
+            (TextType::PatchExe, "Patch Exe"),
+            (TextType::AutoPatch, "Auto Patch"),
+            (TextType::AllInstances, "All games"),
+            (TextType::ChooseInstance, "Choose a game"),
+            (TextType::Inspect, "Inspect"),
+            (TextType::LaunchAndConnect, "Launch & Connect"),
+            (TextType::GameInstance, "Game"),
+            (TextType::CurrentlyPatchedTo, "currently patched to"),
+            (TextType::MatchesListedServer, "matches server"),
+            (TextType::ListedServerKeyDiffers, "listed server with a different key:"),
+            (TextType::ListedServerKeyUnknown, "listed server whose key isn't known:"),
+            (TextType::OfficialServer, "official"),
+            (TextType::UnknownServer, "unknown"),
+            (TextType::RememberPassword, "Remember password"),
+            (TextType::UnlockPasswords, "Passphrase of the saved passwords, Enter to unlock"),
+            (TextType::NewPassphrase, "Choose a passphrase to protect saved passwords, Enter to confirm"),
+            (TextType::RegisterLinkHandler, "Open the ds3os:// links servers post with this loader?"),
+            (TextType::AutoPatched, "Auto patched the game to"),
+            (TextType::WaitingForGame, "Auto patch is waiting for the game to start"),
+            (TextType::NoServerToAutoPatch, "Patch the game once, auto patch then applies that server"),
+            (TextType::ExePatched, "Wrote a patched copy of the game, start it instead of the original executable:"),
         ])),
         (Language::SChinese, HashMap::from([
             (TextType::PasswordRequired, "需要密码"),
             (TextType::PasswordNotRequired, "不需要密码"),
+            (TextType::StaleServerList, "无法连接主服务器，显示缓存的列表，缓存时间："),
+            (TextType::Probing, "检测中..."),
+            (TextType::Reachable, "在线"),
+            (TextType::Unreachable, "无法连接"),
+            (TextType::KeyChangedWarning, "警告：该服务器的公钥与上次连接时不同！可能有人在冒充该服务器。除非服务器管理员告知公钥已更换，否则请不要继续。"),
+            (TextType::PreviousKey, "之前的公钥"),
+            (TextType::CurrentKey, "当前的公钥"),
+            (TextType::InvalidKey, "无效的公钥"),
+            (TextType::PatchSucceeded, "修改成功，游戏现在将连接到"),
+            (TextType::Restore, "恢复"),
+            (TextType::RestoreSucceeded, "游戏已恢复连接官方服务器。"),
+            (TextType::PatchExe, "修改程序"),
+            (TextType::AutoPatch, "自动修改"),
+            (TextType::AllInstances, "所有游戏"),
+            (TextType::ChooseInstance, "选择游戏"),
+            (TextType::Inspect, "查看"),
+            (TextType::LaunchAndConnect, "启动并连接"),
+            (TextType::GameInstance, "游戏"),
+            (TextType::CurrentlyPatchedTo, "当前连接到"),
+            (TextType::MatchesListedServer, "与列表中的服务器一致："),
+            (TextType::ListedServerKeyDiffers, "与列表中的服务器主机名相同但公钥不同："),
+            (TextType::ListedServerKeyUnknown, "与列表中的服务器主机名相同但公钥未知："),
+            (TextType::OfficialServer, "官方服务器"),
+            (TextType::UnknownServer, "未知服务器"),
+            (TextType::RememberPassword, "记住密码"),
+            (TextType::UnlockPasswords, "输入已保存密码的主密码，按回车解锁"),
+            (TextType::NewPassphrase, "设置用于保护已保存密码的主密码，按回车确认"),
+            (TextType::RegisterLinkHandler, "是否使用本加载器打开服务器发布的 ds3os:// 链接？"),
+            (TextType::AutoPatched, "已自动修改游戏，连接到"),
+            (TextType::WaitingForGame, "自动修改正在等待游戏启动"),
+            (TextType::NoServerToAutoPatch, "请先手动修改一次，之后自动修改将使用该服务器"),
+            (TextType::ExePatched, "已写入修改后的游戏副本，请用它代替原版程序启动："),
         ])),
     ]);
+}
+
+// Never Auto, `set_language` resolves it
+static LANGUAGE: RwLock<Language> = RwLock::new(Language::English);
 
-    pub static ref FAIL_REASON_LOCALIZED_STRING: &'static HashMap<FailReason, &'static str> = &FAIL_REASON_LOCALIZED_STRING_[&Language::English];
+/// Strings of the current language, indexed by key.
+pub struct Localized<K: 'static>(fn() -> &'static HashMap<Language, HashMap<K, &'static str>>);
 
-    pub static ref TEXT_LOCALIZED_STRING: &'static HashMap<TextType, &'static str> = &TEXT_LOCALIZED_STRING_[&Language::English];
+impl<K: Eq + Hash> Index<&K> for Localized<K> {
+    type Output = &'static str;
+
+    fn index(&self, key: &K) -> &&'static str {
+        let lang = *LANGUAGE.read().unwrap_or_else(|e| e.into_inner());
+        &(self.0)()[&lang][key]
+    }
 }
+
+fn fail_reason_strings() -> &'static HashMap<Language, HashMap<FailReason, &'static str>> {
+    &FAIL_REASON_LOCALIZED_STRING_
+}
+
+fn text_strings() -> &'static HashMap<Language, HashMap<TextType, &'static str>> {
+    &TEXT_LOCALIZED_STRING_
+}
+
+pub static FAIL_REASON_LOCALIZED_STRING: Localized<FailReason> = Localized(fail_reason_strings);
+pub static TEXT_LOCALIZED_STRING: Localized<TextType> = Localized(text_strings);
+
 pub fn set_language(mut lang: Language) -> Result<()> {
     if lang == Language::Auto {
         let lang_str = get_locale().unwrap_or("en-US".into());
@@ -58,12 +177,7 @@ pub fn set_language(mut lang: Language) -> Result<()> {
             lang = Language::English;
         }
     }
-    unsafe {
-        type F = &'static HashMap<FailReason, &'static str>;
-        type T = &'static HashMap<TextType, &'static str>;
-        *(&(*FAIL_REASON_LOCALIZED_STRING) as *const F as *mut F) = &FAIL_REASON_LOCALIZED_STRING_[&lang];
-        *(&(*TEXT_LOCALIZED_STRING) as *const T as *mut T) = &TEXT_LOCALIZED_STRING_[&lang];
-    };
+    *LANGUAGE.write().unwrap_or_else(|e| e.into_inner()) = lang;
     Ok(())
 }
 
@@ -71,4 +185,36 @@ pub fn set_language(mut lang: Language) -> Result<()> {
 pub enum TextType {
     PasswordRequired,
     PasswordNotRequired,
+    StaleServerList,
+    Probing,
+    Reachable,
+    Unreachable,
+    KeyChangedWarning,
+    PreviousKey,
+    CurrentKey,
+    InvalidKey,
+    PatchSucceeded,
+    Restore,
+    RestoreSucceeded,
+    PatchExe,
+    ExePatched,
+    AutoPatch,
+    AllInstances,
+    ChooseInstance,
+    Inspect,
+    LaunchAndConnect,
+    GameInstance,
+    CurrentlyPatchedTo,
+    MatchesListedServer,
+    ListedServerKeyDiffers,
+    ListedServerKeyUnknown,
+    OfficialServer,
+    UnknownServer,
+    RememberPassword,
+    UnlockPasswords,
+    NewPassphrase,
+    RegisterLinkHandler,
+    AutoPatched,
+    WaitingForGame,
+    NoServerToAutoPatch,
 }
\ No newline at end of file
@@ -1,9 +1,11 @@
 #![windows_subsystem = "windows"]
 use std::fs::File;
 use std::io::Read;
+use std::time::Duration;
 
 use iced::{Application, Settings, window};
 use anyhow::Result;
+use clap::Parser;
 
 mod gui;
 mod api;
@@ -11,17 +13,123 @@ mod patch;
 mod localize;
 mod encrypt;
 mod widgets;
+mod cli;
+mod probe;
+mod pubkey;
+mod pe;
+mod scan;
+mod offline;
+mod watch;
+mod inspect;
+mod launcher;
+mod profile;
+mod imported;
+mod deeplink;
+mod settings;
+mod passwords;
 
-use crate::gui::LoaderMainInterface;
-use crate::localize::Language;
+use crate::gui::{LoaderMainInterface, LoaderFlags};
+use crate::api::{MasterServerPool, RequestPolicy};
+use crate::launcher::Launcher;
+use crate::profile::Game;
+use crate::deeplink::DeepLink;
+use crate::settings::Settings as LoaderSettings;
+
+#[derive(Parser)]
+#[clap(version, about, after_help = cli::EXIT_CODES_HELP)]
+struct Args {
+    /// Master server to fetch servers from, can be given several times. Earlier ones take precedence.
+    /// Defaults to the `masters` in settings.toml.
+    #[clap(long = "master", value_name = "URL", env = "DS3OS_MASTER_SERVERS", value_delimiter = ',')]
+    masters: Vec<String>,
+
+    /// Seconds to wait for a connection to a master server
+    #[clap(long, value_name = "SECS", default_value_t = 5)]
+    connect_timeout: u64,
+
+    /// Seconds to wait for a master server's whole response
+    #[clap(long, value_name = "SECS", default_value_t = 15)]
+    timeout: u64,
+
+    /// How many times a failed server list request is retried
+    #[clap(long, value_name = "N", default_value_t = 3)]
+    retries: u32,
+
+    /// Don't read or write the offline server list cache
+    #[clap(long)]
+    no_cache: bool,
+
+    /// Command that starts the game, quoted like in a shell. Defaults to the one in settings.toml, then to asking Steam.
+    /// A proton script can be run directly.
+    #[clap(long, value_name = "COMMAND", env = "DS3OS_LAUNCH_COMMAND")]
+    launch_command: Option<String>,
+
+    /// Game to patch and list servers for, only ds3 so far. Defaults to the one chosen last.
+    #[clap(long, value_name = "GAME", env = "DS3OS_GAME")]
+    game: Option<Game>,
+
+    /// Print the results of commands as JSON
+    #[clap(long, global = true)]
+    json: bool,
+
+    /// Start with auto patch on, the last chosen server is applied whenever the game starts
+    #[clap(long)]
+    wait_for_game: bool,
+
+    /// ds3os://connect link of a server to open the loader with, see `register-link-handler`
+    #[clap(value_name = "LINK", validator = |s: &str| if DeepLink::is_link(s) { Ok(()) } else { Err("neither a command nor a ds3os:// link") })]
+    link: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<cli::Command>,
+}
 
 fn main() -> Result<()> {
-    localize::set_language(Language::Auto)?;
+    let args = Args::parse();
+    let settings = LoaderSettings::load();
+    localize::set_language(settings.language)?;
+
+    let policy = RequestPolicy {
+        connect_timeout: Duration::from_secs(args.connect_timeout),
+        read_timeout: Duration::from_secs(args.timeout),
+        max_retries: args.retries,
+        ..RequestPolicy::default()
+    };
+    // Those given on the command line replace the saved ones
+    let masters = if args.masters.is_empty() { &settings.masters } else { &args.masters };
+    let masters = MasterServerPool::new(masters.iter().map(|url| url.trim()).filter(|url| !url.is_empty()))?
+        .with_policy(policy);
+    let masters = if args.no_cache { masters.without_cache() } else { masters };
+
+    let launcher = match args.launch_command.as_ref().or(settings.launch_command.as_ref()) {
+        Some(line) => Launcher::from_command(line)?,
+        None => Launcher::Steam,
+    };
+    let link = args.link.as_deref().map(DeepLink::parse).transpose()?;
+    // The link knows which game its server is for
+    let game = link
+        .as_ref()
+        .and_then(|link| link.game)
+        .or(args.game)
+        .unwrap_or(settings.game);
+    let profile = game.profile();
+
+    if let Some(command) = args.command {
+        #[cfg(windows)]
+        attach_console();
+
+        let output = if args.json { cli::Output::Json } else { cli::Output::Human };
+        if let Err(e) = cli::run(command, masters, launcher, profile, output) {
+            output.report_error(&e);
+            std::process::exit(cli::exit_code(&e));
+        }
+        return Ok(());
+    }
 
     let setting = Settings {
         id: None,
         window: window::Settings {
-            size: (800, 600),
+            size: (settings.window.width, settings.window.height),
             position: window::Position::Default, 
             min_size: None,
             max_size: None,
@@ -31,7 +139,14 @@ fn main() -> Result<()> {
             always_on_top: false,
             icon: None,
         },
-        flags: (),
+        flags: LoaderFlags {
+            masters,
+            launcher,
+            game,
+            link,
+            auto_patch: args.wait_for_game,
+            settings,
+        },
         default_font: {
             if cfg!(windows) {
                 let mut buffer = Vec::new();
@@ -44,13 +159,19 @@ fn main() -> Result<()> {
         },
         default_text_size: 16,
         text_multithreading: true,
-        exit_on_close_request: true,
+        // The window size is saved before leaving, see `LoaderMainInterface::should_exit`
+        exit_on_close_request: false,
         antialiasing: false,
         try_opengles_first: false,
     };
 
     LoaderMainInterface::run(setting)?;
     Ok(())
-    //crate::encrypt::test();
-    //Ok(())
+}
+
+// Built for the windows subsystem there's no console, print to the one we were started from
+#[cfg(windows)]
+fn attach_console() {
+    use winapi::um::wincon::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe { AttachConsole(ATTACH_PARENT_PROCESS); }
 }
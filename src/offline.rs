@@ -0,0 +1,140 @@
+// Patching a copy of the executable on disk instead of the running game.
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+use crate::patch::{Patches, GameVersion, ServerInfo};
+use crate::pe;
+use crate::profile::GameProfile;
+use crate::scan;
+
+/// What was changed in a patched copy, enough to turn it back into the original.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub source: PathBuf,
+    pub output: PathBuf,
+    pub version: String,
+    // File offset of the server info block
+    pub offset: usize,
+    pub hostname: String,
+    // SHA-256 of the untouched and the patched executable
+    pub source_hash: String,
+    pub patched_hash: String,
+    // The block as it was in the source
+    pub original: Vec<u8>,
+}
+
+impl Manifest {
+    /// The manifest lives next to the copy it describes.
+    pub fn path_for(output: &Path) -> PathBuf {
+        let mut name = output.file_name().unwrap_or_default().to_os_string();
+        name.push(".ds3os.json");
+        output.with_file_name(name)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    fn save(&self) -> Result<()> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(Self::path_for(&self.output))?), self)?;
+        Ok(())
+    }
+}
+
+/// Where the patched copy goes when no path is given, next to the original.
+pub fn default_output(exe: &Path) -> PathBuf {
+    let stem = exe.file_stem().unwrap_or_default().to_string_lossy();
+    exe.with_file_name(format!("{}.ds3os.exe", stem))
+}
+
+/// Find the server info block in the executable, with the layout it uses and its file offset.
+pub fn locate(profile: &GameProfile, data: &[u8]) -> Result<(GameVersion, usize)> {
+    let header = pe::parse(data)?;
+
+    // Layouts built for this timestamp first, then any other known one
+    let mut candidates: Vec<&GameVersion> = profile.versions.iter().collect();
+    candidates.sort_by_key(|v| !v.pe_timestamps.contains(&header.timestamp));
+    for version in candidates {
+        let found = header.va_to_offset(version.address as u64)
+            .filter(|&offset| data.get(offset..offset + version.patch_size).is_some_and(|block| version.is_server_info(block)));
+        if let Some(offset) = found {
+            return Ok((version.clone(), offset));
+        }
+    }
+
+    let template = profile.versions.last().ok_or_else(|| anyhow!("No {} build is known yet", profile.name))?;
+    let offset = scan::find(data, &scan::server_info_signature(&template.tea_key))
+        .filter(|offset| offset + template.patch_size <= data.len())
+        .ok_or_else(|| anyhow!("No server info block in the executable, it may be packed or encrypted"))?;
+    let address = header.offset_to_va(offset).ok_or_else(|| anyhow!("Server info block isn't in any section"))?;
+    Ok((GameVersion { name: "scanned", address: address as usize, ..template.clone() }, offset))
+}
+
+/// The server info `exe` came with. A copy written by `patch_exe` gives the one of its source.
+pub fn original_server_info(profile: &GameProfile, exe: &Path) -> Result<ServerInfo> {
+    let data = fs::read(exe)?;
+    let (version, offset) = locate(profile, &data)?;
+    match Manifest::load(&Manifest::path_for(exe)) {
+        Ok(manifest) if manifest.offset == offset => ServerInfo::decode(&version, &manifest.original),
+        _ => ServerInfo::decode(&version, &data[offset..]),
+    }
+}
+
+/// Write a copy of `exe` to `output` that connects to `hostname`, the original file is left alone.
+pub fn patch_exe(profile: &GameProfile, exe: &Path, output: &Path, hostname: &str, pubkey: &str) -> Result<Manifest> {
+    if output.exists() && fs::canonicalize(exe)? == fs::canonicalize(output)? {
+        return Err(anyhow!("Output would overwrite the original executable"));
+    }
+
+    let mut data = fs::read(exe)?;
+    let source_hash = scan::hash_bytes(&data);
+    let (version, offset) = locate(profile, &data)?;
+    let block = Patches::encrypt(&version, hostname, pubkey)?;
+
+    let target = &mut data[offset..offset + version.patch_size];
+    let original = target.to_vec();
+    target.copy_from_slice(&block);
+
+    // Make sure what lands on disk decodes to what was asked for
+    let info = ServerInfo::decode(&version, &data[offset..])?;
+    if info.hostname != hostname || info.pubkey != pubkey {
+        return Err(anyhow!("Patched block doesn't decode back to the server"));
+    }
+    fs::write(output, &data)?;
+
+    let manifest = Manifest {
+        source: exe.to_path_buf(),
+        output: output.to_path_buf(),
+        version: version.name.to_string(),
+        offset,
+        hostname: hostname.to_string(),
+        source_hash,
+        patched_hash: scan::hash_bytes(&data),
+        original,
+    };
+    manifest.save()?;
+    Ok(manifest)
+}
+
+/// Put the original block back into `target`, or into the manifest's output when it's None.
+/// Refuses files that aren't byte for byte the copy the manifest was written for.
+pub fn restore_exe(manifest: &Manifest, target: Option<&Path>) -> Result<PathBuf> {
+    let target = target.unwrap_or(&manifest.output);
+    let mut data = fs::read(target)?;
+    if scan::hash_bytes(&data) != manifest.patched_hash {
+        return Err(anyhow!("'{}' isn't the patched copy this manifest describes", target.to_string_lossy()));
+    }
+
+    data.get_mut(manifest.offset..manifest.offset + manifest.original.len())
+        .ok_or_else(|| anyhow!("Manifest offset is past the end of the file"))?
+        .copy_from_slice(&manifest.original);
+    if scan::hash_bytes(&data) != manifest.source_hash {
+        return Err(anyhow!("Restoring wouldn't give back the original executable"));
+    }
+    fs::write(target, &data)?;
+    Ok(target.to_path_buf())
+}
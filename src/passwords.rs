@@ -0,0 +1,280 @@
+// Passwords of the servers the user chose to remember. They go to the desktop keyring when
+// there is one, otherwise to a file encrypted with a passphrase only the user knows.
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::anyhow;
+use argon2::Argon2;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use keyring::Entry;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+const SERVICE: &str = "ds3os-loader";
+const FILE_NAME: &str = "passwords.json";
+const FILE_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum PasswordError {
+    #[error("The saved passwords are locked, enter their passphrase first")]
+    Locked,
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+    #[error("Keyring error: {0}")]
+    Keyring(#[from] keyring::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+pub enum PasswordStore {
+    // The Secret Service, Windows' credential manager or the macOS keychain
+    Keyring,
+    File(PasswordFile),
+}
+
+impl PasswordStore {
+    /// Use the keyring when it answers, the encrypted file otherwise.
+    pub fn open() -> Self {
+        // Asking for a password that was never saved tells whether the keyring works at all
+        let probe = Entry::new(SERVICE, "probe").and_then(|entry| entry.get_password());
+        match probe {
+            Ok(_) | Err(keyring::Error::NoEntry) => PasswordStore::Keyring,
+            // Callers find out through `is_locked`, the file needs a passphrase
+            Err(_) => {
+                let path = dirs::config_dir().map(|d| d.join("ds3os-loader").join(FILE_NAME));
+                PasswordStore::File(PasswordFile::new(path))
+            },
+        }
+    }
+
+    /// Whether the passphrase has to be given before passwords can be read or saved.
+    pub fn is_locked(&self) -> bool {
+        matches!(self, PasswordStore::File(file) if file.key.is_none())
+    }
+
+    /// Whether unlocking sets the passphrase rather than checking it.
+    pub fn is_new(&self) -> bool {
+        matches!(self, PasswordStore::File(file) if !file.exists())
+    }
+
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), PasswordError> {
+        match self {
+            PasswordStore::Keyring => Ok(()),
+            PasswordStore::File(file) => file.unlock(passphrase),
+        }
+    }
+
+    pub fn get(&self, hostname: &str) -> Result<Option<Zeroizing<String>>, PasswordError> {
+        match self {
+            PasswordStore::Keyring => match entry(hostname)?.get_password() {
+                Ok(password) => Ok(Some(Zeroizing::new(password))),
+                Err(keyring::Error::NoEntry) => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            PasswordStore::File(file) => file.get(hostname),
+        }
+    }
+
+    pub fn set(&mut self, hostname: &str, password: &str) -> Result<(), PasswordError> {
+        match self {
+            PasswordStore::Keyring => Ok(entry(hostname)?.set_password(password)?),
+            PasswordStore::File(file) => file.set(hostname, password),
+        }
+    }
+
+    pub fn delete(&mut self, hostname: &str) -> Result<(), PasswordError> {
+        match self {
+            PasswordStore::Keyring => match entry(hostname)?.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+                Err(e) => Err(e.into()),
+            },
+            PasswordStore::File(file) => file.delete(hostname),
+        }
+    }
+}
+
+// Hostnames aren't case sensitive
+fn entry(hostname: &str) -> Result<Entry, PasswordError> {
+    Ok(Entry::new(SERVICE, &hostname.to_ascii_lowercase())?)
+}
+
+// What's written to the file, the passwords only ever hit the disk encrypted
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    version: u32,
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+/// Passwords encrypted with AES-256-GCM, under a key derived from the passphrase with Argon2id.
+pub struct PasswordFile {
+    path: Option<PathBuf>,
+    salt: [u8; 16],
+    key: Option<Zeroizing<[u8; 32]>>,
+    passwords: HashMap<String, Zeroizing<String>>,
+}
+
+impl PasswordFile {
+    fn new(path: Option<PathBuf>) -> Self {
+        PasswordFile { path, salt: [0; 16], key: None, passwords: HashMap::new() }
+    }
+
+    fn exists(&self) -> bool {
+        self.path.as_ref().is_some_and(|path| path.exists())
+    }
+
+    fn read(&self) -> Result<Option<Sealed>, PasswordError> {
+        let path = self.path.as_ref().ok_or_else(|| anyhow!("No config directory"))?;
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                let sealed: Sealed = serde_json::from_str(&text)
+                    .map_err(|e| anyhow!("'{}' is damaged: {}", path.to_string_lossy(), e))?;
+                if sealed.version > FILE_VERSION {
+                    return Err(anyhow!("'{}' was written by a newer loader", path.to_string_lossy()).into());
+                }
+                Ok(Some(sealed))
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow!("Can't read '{}': {}", path.to_string_lossy(), e).into()),
+        }
+    }
+
+    fn unlock(&mut self, passphrase: &str) -> Result<(), PasswordError> {
+        if passphrase.is_empty() {
+            return Err(PasswordError::WrongPassphrase);
+        }
+        match self.read()? {
+            Some(sealed) => {
+                let salt: [u8; 16] = decode(&sealed.salt)?
+                    .try_into()
+                    .map_err(|_| anyhow!("Invalid salt in the password file"))?;
+                let key = derive_key(passphrase, &salt)?;
+                let nonce = decode(&sealed.nonce)?;
+                if nonce.len() != 12 {
+                    return Err(anyhow!("Invalid nonce in the password file").into());
+                }
+                let plain = Zeroizing::new(
+                    Aes256Gcm::new_from_slice(&key[..])
+                        .map_err(|e| anyhow!("{}", e))?
+                        .decrypt(Nonce::from_slice(&nonce), decode(&sealed.data)?.as_slice())
+                        .map_err(|_| PasswordError::WrongPassphrase)?,
+                );
+                let passwords: HashMap<String, String> = serde_json::from_slice(&plain)
+                    .map_err(|e| anyhow!("Password file content is damaged: {}", e))?;
+                self.passwords = passwords.into_iter().map(|(host, pw)| (host, Zeroizing::new(pw))).collect();
+                self.salt = salt;
+                self.key = Some(key);
+            },
+            // The first passphrase given becomes the file's
+            None => {
+                rand::thread_rng().fill_bytes(&mut self.salt);
+                self.key = Some(derive_key(passphrase, &self.salt)?);
+            },
+        }
+        Ok(())
+    }
+
+    fn get(&self, hostname: &str) -> Result<Option<Zeroizing<String>>, PasswordError> {
+        if self.key.is_none() {
+            return Err(PasswordError::Locked);
+        }
+        Ok(self.passwords.get(&hostname.to_ascii_lowercase()).cloned())
+    }
+
+    fn set(&mut self, hostname: &str, password: &str) -> Result<(), PasswordError> {
+        if self.key.is_none() {
+            return Err(PasswordError::Locked);
+        }
+        self.passwords.insert(hostname.to_ascii_lowercase(), Zeroizing::new(password.to_string()));
+        self.save()
+    }
+
+    fn delete(&mut self, hostname: &str) -> Result<(), PasswordError> {
+        if self.key.is_none() {
+            return Err(PasswordError::Locked);
+        }
+        if self.passwords.remove(&hostname.to_ascii_lowercase()).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), PasswordError> {
+        let key = self.key.as_ref().ok_or(PasswordError::Locked)?;
+        let path = self.path.as_ref().ok_or_else(|| anyhow!("No config directory"))?;
+
+        let passwords: HashMap<&str, &str> = self.passwords.iter().map(|(host, pw)| (host.as_str(), pw.as_str())).collect();
+        let plain = Zeroizing::new(serde_json::to_vec(&passwords).map_err(|e| anyhow!("{}", e))?);
+        // A fresh nonce every time, GCM must never reuse one with the same key
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let data = Aes256Gcm::new_from_slice(&key[..])
+            .map_err(|e| anyhow!("{}", e))?
+            .encrypt(Nonce::from_slice(&nonce), plain.as_slice())
+            .map_err(|e| anyhow!("Can't encrypt the passwords: {}", e))?;
+
+        let sealed = Sealed {
+            version: FILE_VERSION,
+            salt: STANDARD.encode(self.salt),
+            nonce: STANDARD.encode(nonce),
+            data: STANDARD.encode(data),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| anyhow!("{}", e))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&sealed).map_err(|e| anyhow!("{}", e))?)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| anyhow!("Can't write '{}': {}", path.to_string_lossy(), e))?;
+        Ok(())
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, PasswordError> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key[..])
+        .map_err(|e| anyhow!("Can't derive the key: {}", e))?;
+    Ok(key)
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, PasswordError> {
+    Ok(STANDARD.decode(value).map_err(|e| anyhow!("Password file content is damaged: {}", e))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_round_trip() {
+        let path = std::env::temp_dir().join(format!("ds3os-loader-test-{}-passwords.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut file = PasswordFile::new(Some(path.clone()));
+        assert!(matches!(file.set("ds3.example.com", "x"), Err(PasswordError::Locked)));
+        file.unlock("correct horse").unwrap();
+        file.set("DS3.example.com", "hunter2").unwrap();
+        file.set("other.example.com", "secret").unwrap();
+        file.delete("other.example.com").unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("hunter2") && !text.contains("ds3.example.com"));
+
+        let mut file = PasswordFile::new(Some(path.clone()));
+        assert!(file.exists());
+        assert!(matches!(file.get("ds3.example.com"), Err(PasswordError::Locked)));
+        assert!(matches!(file.unlock("wrong"), Err(PasswordError::WrongPassphrase)));
+        file.unlock("correct horse").unwrap();
+        assert_eq!(file.get("ds3.example.com").unwrap().as_deref().map(String::as_str), Some("hunter2"));
+        assert!(file.get("other.example.com").unwrap().is_none());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,110 +1,624 @@
-//use std::process::Command;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use anyhow::{Result, anyhow};
-use sysinfo::{ProcessExt, System, SystemExt, ProcessRefreshKind, PidExt};
+use thiserror::Error;
+use serde::{Serialize, Deserialize};
+use sysinfo::{ProcessExt, System, SystemExt, ProcessRefreshKind, PidExt, Pid};
 use lazy_static::lazy_static;
 use bytes::{Bytes, BytesMut, BufMut};
-use process_memory::{Memory, Pid as PidHandle, TryIntoProcessHandle, DataMember};
+use process_memory::{Pid as PidHandle, TryIntoProcessHandle, CopyAddress, PutAddress, ProcessHandle};
+
+use crate::encrypt::Tea32;
+use crate::pe;
+use crate::profile::GameProfile;
+use crate::scan::{self, OffsetCache};
+
+// Every server info block starts with the PEM header of its public key
+pub const SERVER_INFO_PEM_HEADER: &[u8] = b"-----BEGIN RSA PUBLIC KEY-----";
+
+/// Where and how one build of the game keeps its server info block.
+#[derive(Debug, Clone)]
+pub struct GameVersion {
+    pub name: &'static str,
+    // PE TimeDateStamps of the game executable builds sharing this layout
+    pub pe_timestamps: &'static [u32],
+
+    pub address: usize,
+    pub patch_size: usize,
+    // Maximum length of the UTF8 encoded public key.
+    pub max_key_size: usize,
+    // Maximum length of the UTF16 encoded hostname, leave at least 2 bytes from the end of patch_size for nullptr.
+    pub max_host_size: usize,
+    // Offset into the data block that the hostname is placed.
+    pub host_offset: usize,
+    pub host_encoding: HostEncoding,
+    pub tea_key: [u32;4],
+}
 
-use crate::encrypt::tea32_encrypt;
+/// How the hostname is stored in the block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostEncoding {
+    // Null terminated UTF-16, little endian
+    Utf16Le,
+}
 
+impl HostEncoding {
+    pub fn encode(self, hostname: &str) -> Vec<u8> {
+        match self {
+            HostEncoding::Utf16Le => hostname.encode_utf16().flat_map(|twin| {twin.to_le_bytes()} ).collect(),
+        }
+    }
+
+    pub fn decode(self, data: &[u8]) -> Result<String> {
+        match self {
+            HostEncoding::Utf16Le => {
+                let host_data: Vec<u16> = data
+                    .chunks_exact(2)
+                    .map(|twin| u16::from_le_bytes([twin[0], twin[1]]))
+                    .take_while(|c| *c != 0)
+                    .collect();
+                String::from_utf16(&host_data).map_err(|_| anyhow!("Hostname isn't valid UTF-16"))
+            },
+        }
+    }
+}
 
 lazy_static! {
-    #[allow(non_upper_case_globals)]
-    pub static ref SERVER_INFO_TEAENCRYPTION_KEY: [u32;4] = [
-        0x4B694CD6,
-        0x96ADA235,
-        0xEC91D9D4,
-        0x23F562E5
-    ];
-    pub static ref SERVER_INFO_PATCH_SIZE: usize = 520;
+    // Original blocks of the processes we patched, keyed by pid
+    static ref SNAPSHOTS: Mutex<HashMap<u32, Vec<u8>>> = Mutex::new(HashMap::new());
+}
 
-    // Maximum length of the UTF8 encoded public key. 
-    pub static ref SERVER_INFO_MAX_KEY_SIZE: usize= 430;
+impl GameVersion {
+    // Original blocks also survive on disk, for when the loader is restarted while the game runs
+    fn snapshot_path(&self) -> Option<PathBuf> {
+        Some(dirs::data_dir()?
+            .join("ds3os-loader")
+            .join("snapshots")
+            .join(format!("{}-{:X}.bin", self.name, self.address)))
+    }
 
-    // Maximum length of the UTF16 encoded hostname.
-    pub static ref SERVER_INFO_MAX_HOST_SIZE: usize = 85; // Leave at least 2 bytes from the end of ServerInfoPatchSize for nullptr.
+    // Check the block at `address` decrypts to something that looks like server info
+    fn holds_server_info(&self, handle: ProcessHandle) -> bool {
+        let mut head = [0u8; 32];
+        handle.copy_address(self.address, &mut head).is_ok() && self.is_server_info(&head)
+    }
 
-    // Offset into the data block that the hostname is placed.
-    pub static ref SERVER_INFO_HOST_OFFSET: usize = 432;
+    /// Whether the game has mapped and decrypted the block yet.
+    pub fn is_initialized(&self, pid: u32) -> bool {
+        (pid as i32 as PidHandle)
+            .try_into_process_handle()
+            .is_ok_and(|handle| self.holds_server_info(handle))
+    }
 
-    pub static ref SERVER_INFO_ADDRESS: usize = 0x144F4A5B1;
+    // Whether `block` is a whole server info block of this version, with a usable key
+    fn holds_valid_block(&self, block: &[u8]) -> bool {
+        block.len() == self.patch_size && ServerInfo::decode(self, block).is_ok_and(|info| crate::pubkey::parse(&info.pubkey).is_ok())
+    }
+
+    // The snapshot kept on disk, when there is one that fits this version
+    fn read_snapshot(&self) -> Option<Vec<u8>> {
+        let saved = fs::read(self.snapshot_path()?).ok()?;
+        Some(saved).filter(|saved| self.holds_valid_block(saved))
+    }
+
+    pub fn is_server_info(&self, block: &[u8]) -> bool {
+        block.len() >= 32 && Tea32::new(self.tea_key).decrypt(&block[..32]).starts_with(SERVER_INFO_PEM_HEADER)
+    }
+}
+
+/// The decrypted content of a server info block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub pubkey: String,
+    pub hostname: String,
+}
+
+impl ServerInfo {
+    pub fn decode(version: &GameVersion, encrypted: &[u8]) -> Result<ServerInfo> {
+        if encrypted.len() < version.patch_size {
+            return Err(anyhow!("Server info block is truncated"));
+        }
+        let data = Tea32::new(version.tea_key).decrypt(&encrypted[..version.patch_size]);
+
+        let key_data = &data[..version.host_offset];
+        let key_len = key_data.iter().position(|b| *b == 0).unwrap_or(key_data.len());
+        let pubkey = String::from_utf8(key_data[..key_len].to_vec())
+            .map_err(|_| anyhow!("Public key isn't valid UTF-8"))?;
+
+        let hostname = version.host_encoding.decode(&data[version.host_offset..])?;
+
+        Ok(ServerInfo { pubkey, hostname })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    Matches,
+    // Some of the bytes we changed hold what we wrote, the one at `first_diff` doesn't
+    Partial { first_diff: usize },
+    // None of the bytes we changed hold what we wrote
+    Mismatch { first_diff: usize },
+}
+
+impl Verification {
+    // `before` is the block right before the write, which may be a server we patched to earlier. Bytes it
+    // shares with `expected` tell nothing about whether the write landed, every block starts with the same
+    // encrypted PEM header.
+    fn of(expected: &[u8], actual: &[u8], before: &[u8]) -> Self {
+        match expected.iter().zip(actual).position(|(e, a)| e != a) {
+            None => Verification::Matches,
+            Some(first_diff) => {
+                let landed = expected
+                    .iter()
+                    .zip(actual)
+                    .enumerate()
+                    .any(|(i, (e, a))| e == a && before.get(i) != Some(e));
+                if landed {
+                    Verification::Partial { first_diff }
+                }
+                else {
+                    Verification::Mismatch { first_diff }
+                }
+            },
+        }
+    }
+}
+
+/// What the game holds after a patch, read back from its memory.
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub result: Verification,
+    // None when the block can't be decoded
+    pub found: Option<ServerInfo>,
+}
+
+// Writes that span pages are retried one page at a time
+const PAGE_SIZE: usize = 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteFailReason {
+    Permission,
+    Unmapped,
+    // The call went through but the bytes read back aren't the ones written
+    NotWritten,
+    Other(String),
+}
+
+impl From<&io::Error> for WriteFailReason {
+    fn from(e: &io::Error) -> Self {
+        #[cfg(unix)]
+        const UNMAPPED: &[i32] = &[14]; // EFAULT
+        #[cfg(windows)]
+        const UNMAPPED: &[i32] = &[299, 487]; // ERROR_PARTIAL_COPY, ERROR_INVALID_ADDRESS
+        #[cfg(not(any(unix, windows)))]
+        const UNMAPPED: &[i32] = &[];
+
+        match e.raw_os_error() {
+            Some(code) if UNMAPPED.contains(&code) => WriteFailReason::Unmapped,
+            _ if e.kind() == io::ErrorKind::PermissionDenied => WriteFailReason::Permission,
+            _ => WriteFailReason::Other(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for WriteFailReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteFailReason::Permission => write!(f, "permission denied"),
+            WriteFailReason::Unmapped => write!(f, "page not mapped"),
+            WriteFailReason::NotWritten => write!(f, "written bytes didn't stick"),
+            WriteFailReason::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// A range of the block, relative to its start, that couldn't be written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedRange {
+    pub offset: usize,
+    pub len: usize,
+    pub reason: WriteFailReason,
+}
+
+#[derive(Debug, Error)]
+#[error("Couldn't write {}{}", describe_failed(.failed), if *.rolled_back { ", original bytes restored" } else { "" })]
+pub struct WriteError {
+    pub failed: Vec<FailedRange>,
+    // Whether the parts that did get written were put back
+    pub rolled_back: bool,
+}
+
+fn describe_failed(failed: &[FailedRange]) -> String {
+    failed
+        .iter()
+        .map(|r| format!("{:#X}..{:#X} ({})", r.offset, r.offset + r.len, r.reason))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A running process that has the game loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GameProcess {
+    pub pid: u32,
+    // The game executable on disk, when it can be found
+    pub exe: Option<PathBuf>,
+    pub cmdline: Vec<String>,
+    // Seconds since the epoch
+    pub start_time: u64,
+    pub parent: Option<u32>,
+}
+
+impl fmt::Display for GameProcess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs().saturating_sub(self.start_time));
+        write!(f, "PID {}, started {} min ago", self.pid, started / 60)?;
+        if let Some(parent) = self.parent {
+            write!(f, ", parent {}", parent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Working out the layout of one game instance. It can end in a memory scan, so the GUI
+/// runs it off the UI thread.
+#[derive(Debug, Clone)]
+pub struct Detection {
+    pub pid: u32,
+    exe: Option<PathBuf>,
+    profile: &'static GameProfile,
+}
+
+impl Detection {
+    pub fn profile(&self) -> &'static GameProfile {
+        self.profile
+    }
+
+    pub fn run(&self) -> Result<GameVersion> {
+        let versions = &self.profile.versions;
+        if versions.is_empty() {
+            return Err(anyhow!("No {} build is known yet, refusing to patch", self.profile.name));
+        }
+        let pid = self.pid;
+        let exe_path = &self.exe;
+        if let Some(version) = exe_path.as_deref().and_then(|exe| self.profile.version_of_exe(exe)) {
+            return Ok(version.clone());
+        }
+        let timestamp = exe_path.as_ref().and_then(|path| pe::read_header(path).ok()).map(|h| h.timestamp);
+
+        let handle = (pid as i32 as PidHandle).try_into_process_handle()?;
+        if let Some(version) = versions.iter().find(|v| v.holds_server_info(handle)) {
+            return Ok(version.clone());
+        }
+
+        // New builds usually only move the block, so keep the latest layout and look for it
+        let template = versions.last().unwrap();
+        let exe_hash = exe_path.as_ref().and_then(|path| scan::hash_file(path).ok());
+        let mut cache = OffsetCache::load();
+
+        if let Some(address) = exe_hash.as_deref().and_then(|hash| cache.get(hash)) {
+            let version = GameVersion { name: "scanned", address, ..template.clone() };
+            if version.holds_server_info(handle) {
+                return Ok(version);
+            }
+        }
+
+        match scan::find_signature(pid, handle, self.profile.exe_name, &scan::server_info_signature(&template.tea_key))? {
+            Some(address) => {
+                // Failing to cache only means scanning again next time
+                if let Some(hash) = &exe_hash {
+                    let _ = cache.insert(hash, address);
+                }
+                Ok(GameVersion { name: "scanned", address, ..template.clone() })
+            },
+            None => Err(anyhow!("Unknown game version (PE timestamp {:08X?}), refusing to patch", timestamp)),
+        }
+    }
+
+    /// The layout, once the game has set its block up.
+    pub fn ready(&self) -> Option<GameVersion> {
+        self.run().ok().filter(|version| version.is_initialized(self.pid))
+    }
 }
 
 pub struct Patches {
     sys: System,
+    profile: &'static GameProfile,
 }
 
 impl Patches {
-    pub fn new() -> Self {
+    pub fn new(profile: &'static GameProfile) -> Self {
         Patches {
             sys: System::new(),
+            profile,
         }
     }
-    // pub fn run_game() -> Result<()> {
-    //     Command::new("steam")
-    //         .arg("steam://run/374320")
-    //         .spawn()?;
-    //     Ok(())
-    // }
 
-    pub fn find_process(&mut self) -> Result<u32> {
+    pub fn profile(&self) -> &'static GameProfile {
+        self.profile
+    }
+
+    /// Every running game instance, most recently started first.
+    pub fn find_processes(&mut self) -> Vec<GameProcess> {
         self.sys.refresh_processes_specifics(ProcessRefreshKind::new());
-        let mut res: u32 = 0;
 
-        // It seems that process name in linux is "DarkSoulsIII.ex", so keep the last "e" out
-        for process in self.sys.processes_by_name("DarkSoulsIII.ex") {
-            let pid: u32 = process.pid().as_u32();
-            if pid > res {
-                res = pid;
+        let mut found: Vec<GameProcess> = self.sys
+            .processes_by_name(self.profile.process_name)
+            .map(|process| process.pid().as_u32())
+            .filter(|pid| scan::maps_game_image(*pid, self.profile.exe_name))
+            .filter_map(|pid| {
+                let process = self.sys.process(Pid::from_u32(pid))?;
+                Some(GameProcess {
+                    pid,
+                    exe: self.game_exe_path(pid),
+                    cmdline: process.cmd().to_vec(),
+                    start_time: process.start_time(),
+                    parent: process.parent().map(|p| p.as_u32()),
+                })
+            })
+            .collect();
+        found.sort_by_key(|p| std::cmp::Reverse((p.start_time, p.pid)));
+        found
+    }
+
+    /// Whether `ancestor` is somewhere up the parent chain of `pid`.
+    pub fn is_descendant(&self, pid: u32, ancestor: u32) -> bool {
+        let mut current = Pid::from_u32(pid);
+        // Bounded in case a parent chain loops back on itself
+        for _ in 0..64 {
+            match self.sys.process(current).and_then(|p| p.parent()) {
+                Some(parent) if parent.as_u32() == ancestor => return true,
+                Some(parent) => current = parent,
+                None => return false,
             }
         }
-        println!("Game's pid: {}", res);
-        if res == 0 { Err(anyhow!("Can't find process")) } else { Ok(res) }
+        false
     }
 
-    pub fn patch(pid: u32, hostname: &str, pubkey: &str) -> Result<usize> {
+    /// `game_exe_path` without refreshing the whole process list.
+    pub fn exe_of(&mut self, pid: u32) -> Option<PathBuf> {
+        self.sys.refresh_process_specifics(Pid::from_u32(pid), ProcessRefreshKind::new());
+        self.game_exe_path(pid)
+    }
+
+    /// Locate the game executable on disk, under Wine the process' own exe is the preloader.
+    pub fn game_exe_path(&self, pid: u32) -> Option<PathBuf> {
+        let process = self.sys.process(Pid::from_u32(pid))?;
+        let exe_name = self.profile.exe_name;
+        let is_game = |p: &Path| p
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().eq_ignore_ascii_case(exe_name));
+
+        if is_game(process.exe()) {
+            return Some(process.exe().to_path_buf());
+        }
+        process.cmd()
+            .iter()
+            .map(|arg| wine_to_unix_path(arg))
+            .chain(std::iter::once(process.cwd().join(exe_name)))
+            .find(|p| is_game(p) && p.is_file())
+    }
+
+    /// Pick the layout for the running build, refusing builds we know nothing about.
+    pub fn detect_version(&self, pid: u32) -> Result<GameVersion> {
+        self.detection(pid).run()
+    }
+
+    /// What `detect_version` needs to know about `pid`, to run it away from the process list.
+    pub fn detection(&self, pid: u32) -> Detection {
+        Detection { pid, exe: self.game_exe_path(pid), profile: self.profile }
+    }
+
+    /// Write the server info and hand back the block it replaced, for `verify`.
+    pub fn patch(pid: u32, version: &GameVersion, hostname: &str, pubkey: &str) -> Result<Vec<u8>> {
         let handle = (pid as i32 as PidHandle).try_into_process_handle()?;
-        let mut member = DataMember::new_offset(handle, vec![*SERVER_INFO_ADDRESS]);
-
-        let data_block = Self::encrypt(hostname, pubkey)?;
-        let data_len = data_block.len();
-        let mut writed_len = 0;
-
-        data_block.into_iter().for_each(|byte| {
-            match member.write(&byte) {
-                Ok(_) => {
-                    writed_len += 1;
-                    member.set_offset(vec![*SERVER_INFO_ADDRESS + writed_len]);
-                },
-                Err(_) => ()
+
+        let data_block = Self::encrypt(version, hostname, pubkey)?;
+        let before = Self::take_snapshot(pid, version, handle)?;
+
+        Self::write_block(handle, version.address, &data_block, &before)?;
+        Ok(before)
+    }
+
+    /// Write `data` at `address` in one go, or page by page when that fails.
+    /// If only some pages make it, `original` is written back over them.
+    fn write_block(handle: ProcessHandle, address: usize, data: &[u8], original: &[u8]) -> Result<(), WriteError> {
+        if Self::put_checked(handle, address, data).is_ok() {
+            return Ok(());
+        }
+
+        let mut written = Vec::new();
+        let mut failed = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            // Stop each chunk at the next page boundary
+            let len = (PAGE_SIZE - (address + offset) % PAGE_SIZE).min(data.len() - offset);
+            match Self::put_checked(handle, address + offset, &data[offset..offset + len]) {
+                Ok(()) => written.push((offset, len)),
+                Err(reason) => failed.push(FailedRange { offset, len, reason }),
             }
-        });
-        if data_len != writed_len {
-            Err(anyhow!("Exception happened during the patch!"))
+            offset += len;
+        }
+        if failed.is_empty() {
+            return Ok(());
+        }
+
+        let mut rolled_back = false;
+        if !written.is_empty() {
+            rolled_back = written
+                .iter()
+                .all(|&(offset, len)| Self::put_checked(handle, address + offset, &original[offset..offset + len]).is_ok());
+        }
+        Err(WriteError { failed, rolled_back })
+    }
+
+    // `put_address` doesn't say when fewer bytes were written than asked, so read them back
+    fn put_checked(handle: ProcessHandle, address: usize, data: &[u8]) -> Result<(), WriteFailReason> {
+        handle.put_address(address, data).map_err(|e| WriteFailReason::from(&e))?;
+        let mut written = vec![0u8; data.len()];
+        handle.copy_address(address, &mut written).map_err(|e| WriteFailReason::from(&e))?;
+        if written != data {
+            return Err(WriteFailReason::NotWritten);
+        }
+        Ok(())
+    }
+
+    // Keep the block as it was before our first write to this process, and hand back what's there now.
+    // The copy on disk wins: once it exists, anything else at the address is a block we wrote.
+    fn take_snapshot(pid: u32, version: &GameVersion, handle: ProcessHandle) -> Result<Vec<u8>> {
+        let mut current = vec![0u8; version.patch_size];
+        handle.copy_address(version.address, &mut current)?;
+
+        let mut snapshots = SNAPSHOTS.lock().unwrap();
+        if snapshots.contains_key(&pid) {
+            return Ok(current);
+        }
+
+        let original = match version.read_snapshot() {
+            Some(saved) => saved,
+            None => {
+                if !version.holds_valid_block(&current) {
+                    return Err(anyhow!("The server info block can't be decoded, refusing to patch"));
+                }
+                // Nothing gets written before this is saved, so with no snapshot on disk the block is
+                // still the game's own. Without it the block can't be put back once the loader restarts.
+                if let Some(path) = version.snapshot_path() {
+                    path.parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .and_then(|_| fs::write(&path, &current))
+                        .map_err(|e| anyhow!("Can't save snapshot '{}': {}", path.to_string_lossy(), e))?;
+                }
+                current.clone()
+            },
+        };
+        snapshots.insert(pid, original);
+        Ok(current)
+    }
+
+    // The block as it was before we patched the game, from the snapshot kept on disk or this session's one
+    fn original_block(pid: u32, version: &GameVersion) -> Result<Vec<u8>> {
+        if let Some(saved) = version.read_snapshot() {
+            return Ok(saved);
         }
-        else {
-            Ok(writed_len)
+        match SNAPSHOTS.lock().unwrap().get(&pid) {
+            Some(original) if version.holds_valid_block(original) => Ok(original.clone()),
+            Some(_) => Err(anyhow!("Snapshot doesn't fit this game version")),
+            None => Err(anyhow!("No snapshot of the original server info")),
         }
     }
-    fn encrypt(hostname: &str, pubkey: &str) -> Result<Bytes> {
-        let host_data: &[u8] = &hostname.encode_utf16().flat_map(|twin| {twin.to_le_bytes()} ).collect::<Vec<u8>>();
+
+    /// The official server info of this build, known once a game of it has been patched.
+    pub fn original_server_info(pid: u32, version: &GameVersion) -> Option<ServerInfo> {
+        ServerInfo::decode(version, &Self::original_block(pid, version).ok()?).ok()
+    }
+
+    /// Decrypt the server info the game currently holds.
+    pub fn read_server_info(pid: u32, version: &GameVersion) -> Result<ServerInfo> {
+        let handle = (pid as i32 as PidHandle).try_into_process_handle()?;
+        let mut block = vec![0u8; version.patch_size];
+        handle.copy_address(version.address, &mut block)?;
+        ServerInfo::decode(version, &block)
+    }
+
+    /// Write the original block back, from the snapshot kept on disk or this session's one.
+    pub fn restore(pid: u32, version: &GameVersion) -> Result<VerifyReport> {
+        let original = Self::original_block(pid, version)?;
+
+        let handle = (pid as i32 as PidHandle).try_into_process_handle()?;
+        let mut current = vec![0u8; version.patch_size];
+        handle.copy_address(version.address, &mut current)?;
+        Self::write_block(handle, version.address, &original, &current)?;
+
+        let report = Self::compare(pid, version, &original, &current)?;
+        if report.result == Verification::Matches {
+            SNAPSHOTS.lock().unwrap().remove(&pid);
+        }
+        Ok(report)
+    }
+
+    /// Read the block back and compare it to what `patch` should have written over `before`.
+    pub fn verify(pid: u32, version: &GameVersion, hostname: &str, pubkey: &str, before: &[u8]) -> Result<VerifyReport> {
+        Self::compare(pid, version, &Self::encrypt(version, hostname, pubkey)?, before)
+    }
+
+    fn compare(pid: u32, version: &GameVersion, expected: &[u8], before: &[u8]) -> Result<VerifyReport> {
+        let handle = (pid as i32 as PidHandle).try_into_process_handle()?;
+
+        let mut actual = vec![0u8; version.patch_size];
+        handle.copy_address(version.address, &mut actual)?;
+
+        Ok(VerifyReport {
+            result: Verification::of(expected, &actual, before),
+            found: ServerInfo::decode(version, &actual).ok(),
+        })
+    }
+
+    pub(crate) fn encrypt(version: &GameVersion, hostname: &str, pubkey: &str) -> Result<Bytes> {
+        let host_data: &[u8] = &version.host_encoding.encode(hostname);
         let key_data = pubkey.as_bytes();
 
-        if key_data.len() > *SERVER_INFO_MAX_KEY_SIZE {
+        crate::pubkey::parse(pubkey)?;
+
+        if key_data.len() > version.max_key_size {
             return Err(anyhow!("Key's size is too big!"))
         }  
 
-        if host_data.len() > *SERVER_INFO_MAX_HOST_SIZE {
+        if host_data.len() > version.max_host_size {
             return Err(anyhow!("Host's size is too big!"))
         }
 
-        let mut data_block: BytesMut = BytesMut::with_capacity(*SERVER_INFO_PATCH_SIZE);
+        let mut data_block: BytesMut = BytesMut::with_capacity(version.patch_size);
         data_block.put_slice(key_data);
-        data_block.put_bytes(0, *SERVER_INFO_HOST_OFFSET - key_data.len());
+        data_block.put_bytes(0, version.host_offset - key_data.len());
         data_block.put_slice(host_data);
 
-        data_block.resize(*SERVER_INFO_PATCH_SIZE, 0);
+        data_block.resize(version.patch_size, 0);
         
-        Ok(tea32_encrypt(&data_block, &*SERVER_INFO_TEAENCRYPTION_KEY))
+        Ok(Tea32::new(version.tea_key).encrypt(&data_block))
+    }
+}
+
+// Wine maps drive Z: to the unix root
+fn wine_to_unix_path(arg: &str) -> PathBuf {
+    match arg.get(..3) {
+        Some(drive) if drive.eq_ignore_ascii_case("z:\\") => PathBuf::from(arg[2..].replace('\\', "/")),
+        _ => PathBuf::from(arg),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verification_ignores_unchanged_bytes() {
+        let before = [1, 2, 3, 4, 5, 6];
+        let expected = [1, 2, 9, 9, 9, 9];
+        assert_eq!(Verification::of(&expected, &expected, &before), Verification::Matches);
+        // The shared prefix is there whether or not anything was written
+        assert_eq!(Verification::of(&expected, &before, &before), Verification::Mismatch { first_diff: 2 });
+        assert_eq!(Verification::of(&expected, &[1, 2, 9, 9, 5, 6], &before), Verification::Partial { first_diff: 4 });
+        // Without a snapshot any matching byte counts
+        assert_eq!(Verification::of(&expected, &before, &[]), Verification::Partial { first_diff: 2 });
+        // Patching again over an earlier patch, nothing landed even if it shares bytes with the game's own block
+        let patched = [7, 7, 3, 4, 7, 7];
+        assert_eq!(Verification::of(&before, &patched, &patched), Verification::Mismatch { first_diff: 0 });
+    }
+
+    #[test]
+    fn write_block_reads_back() {
+        let handle = (std::process::id() as i32 as PidHandle).try_into_process_handle().unwrap();
+        let mut target = vec![0u8; 3 * PAGE_SIZE];
+        let original = target.clone();
+        let data: Vec<u8> = (0..target.len()).map(|i| i as u8).collect();
+        Patches::write_block(handle, target.as_mut_ptr() as usize, &data, &original).unwrap();
+        assert_eq!(std::hint::black_box(&target), &data);
+    }
+}
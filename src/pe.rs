@@ -0,0 +1,114 @@
+// Just enough of the PE format to identify an executable build and find data in it.
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+
+pub struct PeHeader {
+    // COFF TimeDateStamp, changes with every build the linker produces
+    pub timestamp: u32,
+    // Preferred load address, virtual addresses are relative to it
+    pub image_base: u64,
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub virtual_address: u32,
+    pub virtual_size: u32,
+    pub raw_offset: u32,
+    pub raw_size: u32,
+}
+
+impl PeHeader {
+    /// File offset of the virtual address `va`, if it's backed by data in the file.
+    pub fn va_to_offset(&self, va: u64) -> Option<usize> {
+        let rva = va.checked_sub(self.image_base)?;
+        self.sections.iter().find_map(|s| {
+            let start = s.virtual_address as u64;
+            let delta = rva.checked_sub(start)?;
+            // Only the raw part exists on disk, the rest of the section is zero filled at load
+            let on_disk = if s.virtual_size == 0 { s.raw_size } else { s.raw_size.min(s.virtual_size) };
+            if delta < on_disk as u64 {
+                Some(s.raw_offset as usize + delta as usize)
+            }
+            else {
+                None
+            }
+        })
+    }
+
+    /// Virtual address the file offset `offset` gets loaded at.
+    pub fn offset_to_va(&self, offset: usize) -> Option<u64> {
+        self.sections.iter().find_map(|s| {
+            let delta = (offset as u64).checked_sub(s.raw_offset as u64)?;
+            if delta < s.raw_size as u64 {
+                Some(self.image_base + s.virtual_address as u64 + delta)
+            }
+            else {
+                None
+            }
+        })
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("PE header is truncated"))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("PE header is truncated"))
+}
+
+pub fn parse(data: &[u8]) -> Result<PeHeader> {
+    if u16_at(data, 0)? != 0x5A4D {
+        return Err(anyhow!("Missing MZ signature"));
+    }
+    let pe_offset = u32_at(data, 0x3C)? as usize;
+    if u32_at(data, pe_offset)? != 0x0000_4550 {
+        return Err(anyhow!("Missing PE signature"));
+    }
+    // COFF header follows the signature: Machine(2) NumberOfSections(2) TimeDateStamp(4) ... SizeOfOptionalHeader(2)
+    let coff = pe_offset + 4;
+    let section_count = u16_at(data, coff + 2)? as usize;
+    let timestamp = u32_at(data, coff + 4)?;
+    let optional_size = u16_at(data, coff + 16)? as usize;
+
+    let optional = coff + 20;
+    let image_base = match u16_at(data, optional)? {
+        0x10B => u32_at(data, optional + 28)? as u64,
+        0x20B => u32_at(data, optional + 24)? as u64 | (u32_at(data, optional + 28)? as u64) << 32,
+        magic => return Err(anyhow!("Unknown optional header magic {:#X}", magic)),
+    };
+
+    let table = optional + optional_size;
+    let sections = (0..section_count)
+        .map(|i| {
+            // Each entry is 40 bytes, starting with an 8 byte name
+            let entry = table + i * 40;
+            Ok(Section {
+                virtual_size: u32_at(data, entry + 8)?,
+                virtual_address: u32_at(data, entry + 12)?,
+                raw_size: u32_at(data, entry + 16)?,
+                raw_offset: u32_at(data, entry + 20)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(PeHeader {
+        timestamp,
+        image_base,
+        sections,
+    })
+}
+
+pub fn read_header(path: &Path) -> Result<PeHeader> {
+    let mut data = Vec::with_capacity(4096);
+    File::open(path)?.take(4096).read_to_end(&mut data)?;
+    parse(&data)
+}
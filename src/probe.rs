@@ -0,0 +1,71 @@
+use std::cmp::Ordering;
+use std::time::{Duration, Instant};
+
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+
+// Port the DS3OS login server listens on unless the master says otherwise
+pub const DS3OS_LOGIN_PORT: u16 = 50050;
+
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeStatus {
+    Pending,
+    Reachable(Duration),
+    Unreachable(String),
+    Unresolved(String),
+}
+
+impl ProbeStatus {
+    pub fn rtt(&self) -> Option<Duration> {
+        match self {
+            ProbeStatus::Reachable(rtt) => Some(*rtt),
+            _ => None,
+        }
+    }
+
+    // Reachable servers first, fastest on top, the ones still being probed before the dead ones
+    pub fn rank(&self) -> u8 {
+        match self {
+            ProbeStatus::Reachable(_) => 0,
+            ProbeStatus::Pending => 1,
+            ProbeStatus::Unreachable(_) => 2,
+            ProbeStatus::Unresolved(_) => 3,
+        }
+    }
+}
+
+impl PartialOrd for ProbeStatus {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ProbeStatus {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank().cmp(&other.rank()).then_with(|| self.rtt().cmp(&other.rtt()))
+    }
+}
+
+/// Resolve `hostname` and time a TCP connect to its login port.
+pub async fn probe(hostname: String, port: u16) -> ProbeStatus {
+    let port = if port == 0 { DS3OS_LOGIN_PORT } else { port };
+
+    let addrs: Vec<_> = match timeout(PROBE_TIMEOUT, lookup_host((hostname.as_str(), port))).await {
+        Ok(Ok(addrs)) => addrs.collect(),
+        Ok(Err(e)) => return ProbeStatus::Unresolved(e.to_string()),
+        Err(_) => return ProbeStatus::Unresolved("DNS lookup timed out".into()),
+    };
+
+    let mut last_err = String::from("No address found");
+    for addr in addrs {
+        let start = Instant::now();
+        match timeout(PROBE_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => return ProbeStatus::Reachable(start.elapsed()),
+            Ok(Err(e)) => last_err = e.to_string(),
+            Err(_) => last_err = "Connection timed out".into(),
+        }
+    }
+    ProbeStatus::Unreachable(last_err)
+}
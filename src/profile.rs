@@ -0,0 +1,175 @@
+// Everything that differs between the games the loader can patch.
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::api::Server;
+use crate::patch::{GameVersion, HostEncoding};
+use crate::pe;
+
+// Stored by the same id as on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Game {
+    #[serde(rename = "ds3")]
+    DarkSouls3,
+}
+
+impl Game {
+    // Another game goes in once its server info block has been worked out
+    pub const ALL: [Game; 1] = [Game::DarkSouls3];
+
+    pub fn profile(self) -> &'static GameProfile {
+        match self {
+            Game::DarkSouls3 => &DS3_PROFILE,
+        }
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.profile().name)
+    }
+}
+
+impl FromStr for Game {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Game::ALL
+            .into_iter()
+            .find(|game| game.profile().id.eq_ignore_ascii_case(s))
+            .ok_or_else(|| anyhow!("Unknown game '{}', expected ds3", s))
+    }
+}
+
+/// How to find one game's process and server info block.
+#[derive(Debug)]
+pub struct GameProfile {
+    pub game: Game,
+    // Short name used on the command line and in file names
+    pub id: &'static str,
+    pub name: &'static str,
+    // Name the process is looked up by, Linux cuts it to 15 characters
+    pub process_name: &'static str,
+    pub exe_name: &'static str,
+    pub steam_app_id: u32,
+    // What master servers report as `GameType` for this game's servers
+    pub game_type: &'static str,
+    // Builds whose timestamp isn't listed yet are still accepted when their block
+    // sits at a listed address or can be found by scanning, see `Patches::detect_version`.
+    // The last entry is the template for scanned builds.
+    pub versions: Vec<GameVersion>,
+}
+
+impl GameProfile {
+    /// Whether `server` is a server for this game. Servers that don't say come from
+    /// configs and older masters, so they're DS3 ones.
+    pub fn lists(&self, server: &Server) -> bool {
+        if server.game_type.is_empty() {
+            self.game == Game::DarkSouls3
+        }
+        else {
+            server.game_type.eq_ignore_ascii_case(self.game_type)
+        }
+    }
+
+    /// The build whose executable carries `timestamp`, if it's listed.
+    pub fn version_by_timestamp(&self, timestamp: u32) -> Option<&GameVersion> {
+        self.versions.iter().find(|v| v.pe_timestamps.contains(&timestamp))
+    }
+
+    /// The listed build `exe` is, going by its header alone, so without touching the game's memory.
+    pub fn version_of_exe(&self, exe: &Path) -> Option<&GameVersion> {
+        self.version_by_timestamp(pe::read_header(exe).ok()?.timestamp)
+    }
+}
+
+lazy_static! {
+    static ref DS3_PROFILE: GameProfile = GameProfile {
+        game: Game::DarkSouls3,
+        id: "ds3",
+        name: "Dark Souls III",
+        // It seems that process name in linux is "DarkSoulsIII.ex", so keep the last "e" out
+        process_name: "DarkSoulsIII.ex",
+        exe_name: "DarkSoulsIII.exe",
+        steam_app_id: 374320,
+        game_type: "DarkSouls3",
+        versions: vec![
+            GameVersion {
+                name: "1.15",
+                // The timestamp of the 1.15 executable hasn't been recorded yet, until then the
+                // build is recognized by its block decrypting at this address with this key
+                pe_timestamps: &[],
+                address: 0x144F4A5B1,
+                patch_size: 520,
+                max_key_size: 430,
+                max_host_size: 85,
+                host_offset: 432,
+                host_encoding: HostEncoding::Utf16Le,
+                tea_key: [
+                    0x4B694CD6,
+                    0x96ADA235,
+                    0xEC91D9D4,
+                    0x23F562E5
+                ],
+            },
+        ],
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(name: &'static str, pe_timestamps: &'static [u32]) -> GameVersion {
+        GameVersion { name, pe_timestamps, ..DS3_PROFILE.versions[0].clone() }
+    }
+
+    #[test]
+    fn version_lookup() {
+        let profile = GameProfile {
+            versions: vec![version("old", &[0x5A000000, 0x5A000001]), version("new", &[0x60000000])],
+            ..*DS3_PROFILE
+        };
+        assert_eq!(profile.version_by_timestamp(0x5A000001).map(|v| v.name), Some("old"));
+        assert_eq!(profile.version_by_timestamp(0x60000000).map(|v| v.name), Some("new"));
+        assert!(profile.version_by_timestamp(0x61000000).is_none());
+
+        // A timestamp can only pick one layout
+        for game in Game::ALL {
+            let versions = &game.profile().versions;
+            for (i, v) in versions.iter().enumerate() {
+                assert!(v.pe_timestamps.iter().all(|ts| versions[i + 1..].iter().all(|o| !o.pe_timestamps.contains(ts))));
+            }
+        }
+    }
+
+    // Just the headers of a 64 bit executable without sections
+    fn exe_with_timestamp(timestamp: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 0x40 + 24 + 32];
+        data[..2].copy_from_slice(b"MZ");
+        data[0x3C..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        data[0x40..0x44].copy_from_slice(b"PE\0\0");
+        data[0x48..0x4C].copy_from_slice(&timestamp.to_le_bytes());
+        data[0x58..0x5A].copy_from_slice(&0x20Bu16.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn known_exe_needs_no_scan() {
+        let profile = GameProfile { versions: vec![version("old", &[0x5A000000]), version("new", &[0x60000000])], ..*DS3_PROFILE };
+        let exe = std::env::temp_dir().join(format!("ds3os-loader-test-{}-profile.exe", std::process::id()));
+
+        std::fs::write(&exe, exe_with_timestamp(0x60000000)).unwrap();
+        assert_eq!(profile.version_of_exe(&exe).map(|v| v.name), Some("new"));
+        std::fs::write(&exe, exe_with_timestamp(0x61000000)).unwrap();
+        assert!(profile.version_of_exe(&exe).is_none());
+        std::fs::write(&exe, b"not an executable").unwrap();
+        assert!(profile.version_of_exe(&exe).is_none());
+        std::fs::remove_file(exe).unwrap();
+    }
+}
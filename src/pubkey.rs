@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow};
+use rsa::RsaPublicKey;
+use rsa::pkcs1::{DecodeRsaPublicKey, EncodeRsaPublicKey};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+/// Parse the key the same way the game does, it only accepts PKCS#1 "RSA PUBLIC KEY" PEMs.
+pub fn parse(pubkey: &str) -> Result<RsaPublicKey> {
+    RsaPublicKey::from_pkcs1_pem(pubkey.trim())
+        .map_err(|e| anyhow!("Not a valid 'RSA PUBLIC KEY' PEM: {}", e))
+}
+
+/// SHA-256 of the key's DER encoding, as colon separated hex.
+pub fn fingerprint(pubkey: &str) -> Result<String> {
+    let der = parse(pubkey)?
+        .to_pkcs1_der()
+        .map_err(|e| anyhow!("Can't encode public key: {}", e))?;
+    Ok(Sha256::digest(der.as_bytes())
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trust {
+    // Never seen this hostname before
+    New,
+    Known,
+    // The hostname presented a different key last time
+    Changed { previous: String },
+}
+
+/// Fingerprints of the keys seen per hostname, kept across sessions (trust on first use).
+#[derive(Serialize, Deserialize, Default)]
+pub struct KnownKeys {
+    keys: HashMap<String, String>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl KnownKeys {
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("ds3os-loader").join("known_keys.json"))
+    }
+
+    pub fn load() -> Self {
+        let path = Self::default_path();
+        let mut known: KnownKeys = path
+            .as_ref()
+            .and_then(|p| File::open(p).ok())
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok())
+            .unwrap_or_default();
+        known.path = path;
+        known
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, hostname: &str) -> Option<&str> {
+        self.keys.get(&hostname.to_lowercase()).map(String::as_str)
+    }
+
+    pub fn check(&self, hostname: &str, fingerprint: &str) -> Trust {
+        match self.get(hostname) {
+            None => Trust::New,
+            Some(known) if known == fingerprint => Trust::Known,
+            Some(known) => Trust::Changed { previous: known.to_string() },
+        }
+    }
+
+    pub fn remember(&mut self, hostname: &str, fingerprint: &str) -> Result<()> {
+        self.keys.insert(hostname.to_lowercase(), fingerprint.to_string());
+        self.save()
+    }
+}
@@ -0,0 +1,194 @@
+// Locating the server info block in memory when the build's address isn't known.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+use process_memory::{CopyAddress, ProcessHandle};
+use sha2::{Digest, Sha256};
+
+use crate::encrypt::Tea32;
+use crate::patch::SERVER_INFO_PEM_HEADER;
+
+// Bytes read from the target per call
+const SCAN_CHUNK_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    // Backed by the game executable itself
+    pub is_image: bool,
+}
+
+#[cfg(target_os = "linux")]
+pub fn readable_regions(pid: u32, _handle: ProcessHandle, image_name: &str) -> Result<Vec<Region>> {
+    let image_name = image_name.to_lowercase();
+    let maps = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    Ok(maps
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            let perms = fields.next()?;
+            if !perms.starts_with('r') {
+                return None;
+            }
+            let path = fields.nth(3).unwrap_or_default();
+            Some(Region {
+                start: usize::from_str_radix(start, 16).ok()?,
+                end: usize::from_str_radix(end, 16).ok()?,
+                is_image: path.to_lowercase().ends_with(&image_name),
+            })
+        })
+        .collect())
+}
+
+#[cfg(windows)]
+pub fn readable_regions(_pid: u32, handle: ProcessHandle, _image_name: &str) -> Result<Vec<Region>> {
+    use std::mem::{size_of, zeroed};
+    use winapi::um::memoryapi::VirtualQueryEx;
+    use winapi::um::winnt::{
+        MEMORY_BASIC_INFORMATION, MEM_COMMIT, MEM_IMAGE, PAGE_GUARD, PAGE_NOACCESS,
+        PAGE_READONLY, PAGE_READWRITE, PAGE_WRITECOPY, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY,
+    };
+
+    let readable = PAGE_READONLY | PAGE_READWRITE | PAGE_WRITECOPY | PAGE_EXECUTE_READ | PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY;
+    let mut regions = Vec::new();
+    let mut addr: usize = 0;
+    loop {
+        let mut info: MEMORY_BASIC_INFORMATION = unsafe { zeroed() };
+        let len = unsafe { VirtualQueryEx(handle.0, addr as _, &mut info, size_of::<MEMORY_BASIC_INFORMATION>()) };
+        if len == 0 {
+            break;
+        }
+        let start = info.BaseAddress as usize;
+        let end = start + info.RegionSize;
+        if info.State == MEM_COMMIT && info.Protect & (PAGE_GUARD | PAGE_NOACCESS) == 0 && info.Protect & readable != 0 {
+            regions.push(Region { start, end, is_image: info.Type == MEM_IMAGE });
+        }
+        if end <= addr {
+            break;
+        }
+        addr = end;
+    }
+    Ok(regions)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn readable_regions(_pid: u32, _handle: ProcessHandle, _image_name: &str) -> Result<Vec<Region>> {
+    Err(anyhow!("Memory scanning isn't supported on this platform"))
+}
+
+/// Whether the process has the game executable mapped, Wine and Proton wrappers share its name but not its image.
+#[cfg(target_os = "linux")]
+pub fn maps_game_image(pid: u32, exe_name: &str) -> bool {
+    let exe_name = exe_name.to_lowercase();
+    fs::read_to_string(format!("/proc/{}/maps", pid))
+        .is_ok_and(|maps| maps.lines().any(|line| line.to_lowercase().ends_with(&exe_name)))
+}
+
+// Elsewhere the process of that name is the game itself
+#[cfg(not(target_os = "linux"))]
+pub fn maps_game_image(_pid: u32, _exe_name: &str) -> bool {
+    true
+}
+
+/// The encrypted form of the PEM header every server info block starts with.
+/// TEA works on independent 8 byte blocks, so it's the same whatever key or host follows.
+pub fn server_info_signature(tea_key: &[u32;4]) -> Vec<u8> {
+    let len = SERVER_INFO_PEM_HEADER.len() / 8 * 8;
+    Tea32::new(*tea_key).encrypt(&SERVER_INFO_PEM_HEADER[..len]).to_vec()
+}
+
+/// Search the readable memory of the process for `signature`, regions of the `image_name` executable first.
+pub fn find_signature(pid: u32, handle: ProcessHandle, image_name: &str, signature: &[u8]) -> Result<Option<usize>> {
+    let mut regions = readable_regions(pid, handle, image_name)?;
+    regions.sort_by_key(|r| !r.is_image);
+
+    let mut buf = vec![0u8; SCAN_CHUNK_SIZE];
+    for region in regions {
+        let mut addr = region.start;
+        while addr < region.end {
+            let len = (region.end - addr).min(SCAN_CHUNK_SIZE);
+            // Pages can disappear while we scan, just skip them
+            if handle.copy_address(addr, &mut buf[..len]).is_ok() {
+                if let Some(pos) = find(&buf[..len], signature) {
+                    return Ok(Some(addr + pos));
+                }
+            }
+            if addr + len >= region.end {
+                break;
+            }
+            // Overlap chunks so a match crossing their boundary isn't missed
+            addr += len - (signature.len() - 1);
+        }
+    }
+    Ok(None)
+}
+
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let first = *needle.first()?;
+    haystack
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == first)
+        .map(|(i, _)| i)
+        .find(|i| haystack[*i..].starts_with(needle))
+}
+
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+pub fn hash_bytes(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+fn to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Addresses found by scanning, keyed by the SHA-256 of the executable.
+#[derive(Default)]
+pub struct OffsetCache {
+    offsets: HashMap<String, usize>,
+    path: Option<PathBuf>,
+}
+
+impl OffsetCache {
+    pub fn load() -> Self {
+        let path = dirs::cache_dir().map(|d| d.join("ds3os-loader").join("offsets.json"));
+        let offsets = path
+            .as_ref()
+            .and_then(|p| File::open(p).ok())
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok())
+            .unwrap_or_default();
+        OffsetCache { offsets, path }
+    }
+
+    pub fn get(&self, exe_hash: &str) -> Option<usize> {
+        self.offsets.get(exe_hash).copied()
+    }
+
+    pub fn insert(&mut self, exe_hash: &str, address: usize) -> Result<()> {
+        self.offsets.insert(exe_hash.to_string(), address);
+        let path = self.path.as_ref().ok_or_else(|| anyhow!("No cache directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &self.offsets)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+// What the loader remembers between runs, kept in settings.toml in the config directory.
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize, Serializer};
+use zeroize::Zeroize;
+
+use crate::api::Server;
+use crate::localize::Language;
+use crate::profile::Game;
+
+// Bump when the layout changes, and teach `upgrade` the step from the previous one
+pub const SETTINGS_VERSION: u32 = 1;
+const FILE_NAME: &str = "settings.toml";
+
+const DEFAULT_WIDTH: u32 = 800;
+const DEFAULT_HEIGHT: u32 = 600;
+// Anything smaller is a window that was minimized or went wrong, don't come back to it
+const MIN_WIDTH: u32 = 320;
+const MIN_HEIGHT: u32 = 240;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub language: Language,
+    pub game: Game,
+    // Used when none are given on the command line
+    pub masters: Vec<String>,
+    pub launch_command: Option<String>,
+    // Hostname of the server selected last
+    pub selected_server: Option<String>,
+    // Hostnames whose password is kept in the password store, see `passwords`
+    pub remembered_passwords: Vec<String>,
+    // Whether ds3os:// links open with the loader, None until the user was asked
+    pub link_handler: Option<bool>,
+    // Tables have to come after plain values in TOML
+    pub window: WindowSettings,
+    // Servers imported from .ds3osconfig files, without their passwords
+    #[serde(serialize_with = "without_passwords")]
+    pub imported: Vec<Server>,
+
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    // Written by a newer loader, which may keep things we'd drop
+    #[serde(skip)]
+    read_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+    pub width: u32,
+    pub height: u32,
+    // Position of the divider between the server list and the details
+    pub divider: Option<u16>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: SETTINGS_VERSION,
+            language: Language::Auto,
+            game: Game::DarkSouls3,
+            masters: Vec::new(),
+            launch_command: None,
+            selected_server: None,
+            remembered_passwords: Vec::new(),
+            link_handler: None,
+            window: WindowSettings::default(),
+            imported: Vec::new(),
+            path: None,
+            read_only: false,
+        }
+    }
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        WindowSettings { width: DEFAULT_WIDTH, height: DEFAULT_HEIGHT, divider: None }
+    }
+}
+
+impl Settings {
+    /// Load the settings, falling back to the defaults for whatever can't be read.
+    pub fn load() -> Self {
+        match dirs::config_dir() {
+            Some(dir) => Self::load_from(dir.join("ds3os-loader").join(FILE_NAME)),
+            None => {
+                eprintln!("No config directory, settings won't be kept");
+                Settings::default()
+            },
+        }
+    }
+
+    fn load_from(path: PathBuf) -> Self {
+        let mut settings = match fs::read_to_string(&path) {
+            Ok(text) => match parse(&text) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    // Keep the broken file around rather than writing over it
+                    let backup = path.with_extension("toml.bak");
+                    eprintln!("Can't read '{}', moved it to '{}': {}", path.to_string_lossy(), backup.to_string_lossy(), e);
+                    let _ = fs::rename(&path, &backup);
+                    Settings::default()
+                },
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => Settings::default(),
+            Err(e) => {
+                eprintln!("Can't read '{}', using the defaults: {}", path.to_string_lossy(), e);
+                Settings { read_only: true, ..Settings::default() }
+            },
+        };
+        settings.path = Some(path);
+        settings
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!("Settings were written by a newer loader, not overwriting them"));
+        }
+        let path = self.path.as_ref().ok_or_else(|| anyhow!("No config directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self)?;
+        // Write aside and rename, so a crash never leaves half a file
+        let tmp = path.with_extension("toml.tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Add `servers`, replacing those already imported with the same hostname.
+    /// Their passwords are left out, remembering them is up to the password store.
+    pub fn add_imported(&mut self, servers: &[Server]) {
+        for server in servers {
+            self.remove_imported(&server.hostname);
+            let mut server = server.clone();
+            server.passwd.zeroize();
+            self.imported.push(server);
+        }
+    }
+
+    pub fn remove_imported(&mut self, hostname: &str) {
+        self.imported.retain(|s| !s.hostname.eq_ignore_ascii_case(hostname));
+    }
+
+    pub fn remembers_password(&self, hostname: &str) -> bool {
+        self.remembered_passwords.iter().any(|host| host.eq_ignore_ascii_case(hostname))
+    }
+
+    pub fn set_remember_password(&mut self, hostname: &str, remember: bool) {
+        self.remembered_passwords.retain(|host| !host.eq_ignore_ascii_case(hostname));
+        if remember {
+            self.remembered_passwords.push(hostname.to_string());
+        }
+    }
+
+    // Drop what can't be used, so a hand edited file can't break the loader
+    fn sanitize(&mut self) {
+        if self.window.width < MIN_WIDTH || self.window.height < MIN_HEIGHT {
+            self.window.width = DEFAULT_WIDTH;
+            self.window.height = DEFAULT_HEIGHT;
+        }
+        let width = self.window.width;
+        self.window.divider = self.window.divider.filter(|&pos| u32::from(pos) < width);
+        self.masters.retain(|url| !url.trim().is_empty());
+        self.launch_command = self.launch_command.take().filter(|line| !line.trim().is_empty());
+        self.selected_server = self.selected_server.take().filter(|host| !host.is_empty());
+        self.imported.retain(|s| !s.hostname.trim().is_empty());
+        // A hand edited file may hold some, they're gone with the next save
+        self.imported.iter_mut().for_each(|s| s.passwd.zeroize());
+    }
+}
+
+fn parse(text: &str) -> Result<Settings> {
+    let mut value: toml::Value = toml::from_str(text)?;
+    // Files written by hand may not say
+    let version = match value.get("version") {
+        None => 0,
+        Some(v) => v
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| anyhow!("Invalid settings version {}", v))?,
+    };
+
+    let newer = version > SETTINGS_VERSION;
+    if newer {
+        eprintln!("Settings are from a newer loader (version {}), they won't be saved", version);
+    }
+    else {
+        upgrade(&mut value, version)?;
+    }
+
+    let mut settings: Settings = value.try_into()?;
+    settings.version = version.max(SETTINGS_VERSION);
+    settings.read_only = newer;
+    settings.sanitize();
+    Ok(settings)
+}
+
+// Bring settings written by an older loader to the current layout, one version at a time
+fn upgrade(_value: &mut toml::Value, from: u32) -> Result<()> {
+    for version in from..SETTINGS_VERSION {
+        match version {
+            // No version field, the layout is the first one
+            0 => {},
+            _ => return Err(anyhow!("Don't know how to upgrade settings version {}", version)),
+        }
+    }
+    Ok(())
+}
+
+// Passwords never hit settings.toml, not even those a hand edited file brought in
+fn without_passwords<S: Serializer>(servers: &[Server], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(servers.iter().map(|s| Server { passwd: String::new(), ..s.clone() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ds3os-loader-settings-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn server(hostname: &str) -> Server {
+        serde_json::from_value(serde_json::json!({ "Hostname": hostname, "Name": "Test", "Password": "secret" })).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_dir("round-trip");
+        let path = dir.join(FILE_NAME);
+        let mut settings = Settings::load_from(path.clone());
+        settings.language = Language::SChinese;
+        settings.masters = vec!["https://master.example.com/".into()];
+        settings.selected_server = Some("ds3.example.com".into());
+        settings.window = WindowSettings { width: 1024, height: 700, divider: Some(500) };
+        settings.add_imported(&[server("ds3.example.com"), server("other.example.com")]);
+        settings.add_imported(&[server("DS3.example.com")]);
+        settings.save().unwrap();
+
+        let loaded = Settings::load_from(path.clone());
+        assert_eq!(loaded.language, Language::SChinese);
+        assert_eq!(loaded.masters, settings.masters);
+        assert_eq!(loaded.selected_server.as_deref(), Some("ds3.example.com"));
+        assert_eq!(loaded.window, settings.window);
+        let hosts: Vec<_> = loaded.imported.iter().map(|s| s.hostname.as_str()).collect();
+        assert_eq!(hosts, ["other.example.com", "DS3.example.com"]);
+        assert!(loaded.imported.iter().all(|s| s.passwd.is_empty()));
+
+        // Even when put in by hand, passwords aren't written
+        settings.imported[0].passwd = "secret".into();
+        settings.save().unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("secret"));
+        fs::write(&path, "[[imported]]\nHostname = \"old.example.com\"\nPassword = \"secret\"\n").unwrap();
+        assert!(Settings::load_from(path).imported[0].passwd.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sanitizes() {
+        // Unversioned and partly broken files keep what's usable
+        let settings = parse("language = \"english\"\n[window]\nwidth = 0\nheight = 10\ndivider = 9000\n").unwrap();
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert_eq!(settings.language, Language::English);
+        assert_eq!(settings.window, WindowSettings::default());
+        assert!(parse("version = \"two\"").is_err());
+    }
+
+    #[test]
+    fn newer_version_isnt_overwritten() {
+        let dir = temp_dir("newer");
+        let path = dir.join(FILE_NAME);
+        let text = "version = 99\nfuture_option = true\nlanguage = \"schinese\"\n";
+        fs::write(&path, text).unwrap();
+        let settings = Settings::load_from(path.clone());
+        assert_eq!(settings.language, Language::SChinese);
+        assert!(settings.save().is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), text);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+// Waiting for the game to start so the last chosen server can be applied to it.
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+
+use crate::patch::{Patches, GameVersion, GameProcess, ServerInfo, Detection};
+use crate::profile::GameProfile;
+
+// How often the process list is polled
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+// Detection can end in a memory scan, don't run it on every poll
+const DETECT_RETRY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    NotRunning,
+    // Found, but the server info block isn't there yet
+    Starting(u32),
+    // A new game process is ready to be patched
+    Ready(u32, GameVersion),
+    // Nothing changed since the game was patched
+    Patched(u32),
+}
+
+/// What a poll got to before the version of a new process has to be detected.
+#[derive(Debug, Clone)]
+pub enum WatchStep {
+    Done(WatchEvent),
+    // Run it and hand the result to `detected`
+    Detect(Detection),
+}
+
+/// Follows the game process across restarts, handing out each new one once it's initialized.
+/// A process handed out as ready is left alone until `patched` tells how it went.
+#[derive(Default)]
+pub struct GameWatcher {
+    // Patched and verified
+    handled: Option<u32>,
+    // Not to be looked at again before then
+    starting: Option<(u32, Instant)>,
+}
+
+impl GameWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn poll(&mut self, patches: &mut Patches) -> WatchEvent {
+        self.poll_with(patches, |_, found| found.first().map(|p| p.pid))
+    }
+
+    /// Like `poll`, with `select` choosing which of the running instances, newest first, to follow.
+    pub fn poll_with<F>(&mut self, patches: &mut Patches, select: F) -> WatchEvent
+    where
+        F: FnOnce(&Patches, &[GameProcess]) -> Option<u32>,
+    {
+        match self.step_with(patches, select) {
+            WatchStep::Done(event) => event,
+            WatchStep::Detect(detection) => self.detected(detection.pid, detection.ready()),
+        }
+    }
+
+    pub fn step(&mut self, patches: &mut Patches) -> WatchStep {
+        self.step_with(patches, |_, found| found.first().map(|p| p.pid))
+    }
+
+    /// Like `poll_with`, leaving the detection of a new process to the caller.
+    pub fn step_with<F>(&mut self, patches: &mut Patches, select: F) -> WatchStep
+    where
+        F: FnOnce(&Patches, &[GameProcess]) -> Option<u32>,
+    {
+        let found = patches.find_processes();
+        let pid = match select(patches, &found) {
+            Some(pid) => pid,
+            None => {
+                self.handled = None;
+                self.starting = None;
+                return WatchStep::Done(WatchEvent::NotRunning);
+            },
+        };
+        if self.handled == Some(pid) {
+            return WatchStep::Done(WatchEvent::Patched(pid));
+        }
+        if let Some((starting, next_try)) = self.starting {
+            if starting == pid && Instant::now() < next_try {
+                return WatchStep::Done(WatchEvent::Starting(pid));
+            }
+        }
+        WatchStep::Detect(patches.detection(pid))
+    }
+
+    /// Take in what `Detection::ready` found for `pid`.
+    pub fn detected(&mut self, pid: u32, version: Option<GameVersion>) -> WatchEvent {
+        self.starting = Some((pid, Instant::now() + DETECT_RETRY));
+        match version {
+            Some(version) => WatchEvent::Ready(pid, version),
+            None => WatchEvent::Starting(pid),
+        }
+    }
+
+    /// Whether patching the ready process `pid` stuck. Until it does, it's tried again after a while.
+    pub fn patched(&mut self, pid: u32, verified: bool) {
+        if verified {
+            self.handled = Some(pid);
+            self.starting = None;
+        }
+        else {
+            self.starting = Some((pid, Instant::now() + DETECT_RETRY));
+        }
+    }
+}
+
+fn last_server_path(profile: &GameProfile) -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("ds3os-loader").join(format!("last_server.{}.json", profile.id)))
+}
+
+/// The server the game was last successfully patched to.
+pub fn load_last_server(profile: &GameProfile) -> Option<ServerInfo> {
+    let file = File::open(last_server_path(profile)?).ok()?;
+    serde_json::from_reader(BufReader::new(file)).ok()
+}
+
+pub fn save_last_server(profile: &GameProfile, info: &ServerInfo) -> Result<()> {
+    let path = last_server_path(profile).ok_or_else(|| anyhow!("No data directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), info)?;
+    Ok(())
+}
@@ -1,13 +1,15 @@
-use iced::{button, Button, Element, Length, Text, Alignment, Column, Scrollable, scrollable, TextInput, text_input};
+use iced::{button, Button, Element, Length, Text, Alignment, Column, Scrollable, scrollable, TextInput, text_input, Checkbox};
 use iced_aw::FloatingButton;
 
 use crate::api::Server;
 use crate::gui::{ICON_FONT, Icon};
 use crate::localize::{TEXT_LOCALIZED_STRING, TextType::*};
+use crate::pubkey;
 pub struct DetailPanel {
     srcollable: scrollable::State,
     patch_btn: button::State,
     passwd_input: text_input::State,
+    passphrase_input: text_input::State,
 }
 
 impl DetailPanel {
@@ -16,19 +18,22 @@ impl DetailPanel {
             srcollable: scrollable::State::new(),
             patch_btn: button::State::new(),
             passwd_input: text_input::State::new(),
+            passphrase_input: text_input::State::new(),
         }
     }
 
-    pub fn view(&mut self, server: Server, passwd: &str) -> Element<crate::gui::Message> {
-        let name_text = Text::new(&format!("{}: {}", "Name", server.name));
+    /// `passphrase` is given while the saved passwords are locked: what's typed so far,
+    /// and whether it's a new passphrase being chosen.
+    pub fn view(&mut self, server: Server, passwd: &str, known_fingerprint: Option<&str>, remember: bool, passphrase: Option<(&str, bool)>) -> Element<'_, crate::gui::Message> {
+        let name_text = Text::new(format!("{}: {}", "Name", server.name));
 
-        let hostname_text = Text::new(&format!("{}: {}", "Hostname", server.hostname));
+        let hostname_text = Text::new(format!("{}: {}", "Hostname", server.hostname));
 
-        let private_hostname_text = Text::new(&format!("{}: {}", "Private Hostname", server.private_hostname));
+        let private_hostname_text = Text::new(format!("{}: {}", "Private Hostname", server.private_hostname));
 
-        let player_count_text = Text::new(&format!("{}: {}", "Player Count", server.player_count));
+        let player_count_text = Text::new(format!("{}: {}", "Player Count", server.player_count));
 
-        let password_required_text = Text::new(&format!("{}: {}", "Password: ", 
+        let password_required_text = Text::new(format!("{}: {}", "Password: ", 
             if server.password_required {
                 TEXT_LOCALIZED_STRING[&PasswordRequired]
             }
@@ -37,9 +42,9 @@ impl DetailPanel {
             }
         ));
 
-        let description_text = Text::new(&format!("{}: {}", "Description", server.description));
+        let description_text = Text::new(format!("{}: {}", "Description", server.description));
 
-        let col = Column::new()
+        let mut col = Column::new()
             .push(name_text)
             .push(hostname_text)
             .push(private_hostname_text)
@@ -49,6 +54,34 @@ impl DetailPanel {
             .spacing(10)
             .align_items(Alignment::Start);
 
+        // Only v2 masters report these
+        if !server.game_type.is_empty() {
+            col = col.push(Text::new(format!("{}: {}", "Game", server.game_type)));
+        }
+        if !server.version.is_empty() {
+            col = col.push(Text::new(format!("{}: {}", "Version", server.version)));
+        }
+        if !server.master.is_empty() {
+            col = col.push(Text::new(format!("{}: {}", "Master Server", server.master)));
+        }
+
+        if !server.pubkey.is_empty() {
+            match pubkey::fingerprint(&server.pubkey) {
+                Ok(fingerprint) => {
+                    col = col.push(Text::new(format!("{}: {}", "Key Fingerprint", fingerprint)));
+                    if known_fingerprint.is_some_and(|known| known != fingerprint) {
+                        col = col.push(Text::new(TEXT_LOCALIZED_STRING[&KeyChangedWarning]).color([0.8, 0.0, 0.0]));
+                    }
+                },
+                Err(_) => {
+                    col = col.push(Text::new(TEXT_LOCALIZED_STRING[&InvalidKey]).color([0.8, 0.0, 0.0]));
+                }
+            }
+        }
+        else if let Some(known) = known_fingerprint {
+            col = col.push(Text::new(format!("{}: {}", "Key Fingerprint (last seen)", known)));
+        }
+
         let scrollable = Scrollable::new(&mut self.srcollable)
             .push(col)
             .height(Length::Fill)
@@ -57,12 +90,29 @@ impl DetailPanel {
         let passwd_input = TextInput::new(&mut self.passwd_input,
             "Password",
             passwd,
-            |s| crate::gui::Message::PasswordInput(s)
+            crate::gui::Message::PasswordInput
         ).size(32);
 
-        let underlay = Column::new()
+        let remember_check = Checkbox::new(remember, TEXT_LOCALIZED_STRING[&RememberPassword], crate::gui::Message::RememberPassword);
+
+        let mut underlay = Column::new()
             .push(scrollable)
-            .push(passwd_input);
+            .push(passwd_input)
+            .push(remember_check)
+            .spacing(5);
+
+        if let Some((passphrase, new)) = passphrase {
+            let placeholder = TEXT_LOCALIZED_STRING[if new { &NewPassphrase } else { &UnlockPasswords }];
+            let passphrase_input = TextInput::new(&mut self.passphrase_input,
+                placeholder,
+                passphrase,
+                crate::gui::Message::PassphraseInput
+            )
+            .password()
+            .on_submit(crate::gui::Message::UnlockPasswords)
+            .size(24);
+            underlay = underlay.push(passphrase_input);
+        }
 
         FloatingButton::new(&mut self.patch_btn, underlay, |state| {
                 Button::new(
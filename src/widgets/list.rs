@@ -1,4 +1,9 @@
-use crate::api::MasterServerApi;
+use std::time::Duration;
+
+use crate::api::{MasterServerPool, ServerListing};
+use crate::localize::{TEXT_LOCALIZED_STRING, TextType};
+use crate::probe::{probe, ProbeStatus};
+use crate::profile::GameProfile;
 
 use {
     crate::api::Server,
@@ -13,6 +18,7 @@ pub struct ServerRow {
     pub id: usize,
     pub server: Server,
     pub is_manual: bool,
+    pub probe: ProbeStatus,
 
     server_btn: button::State,
 }
@@ -30,11 +36,32 @@ impl ServerRow {
             id,
             server,
             is_manual,
+            probe: ProbeStatus::Pending,
             server_btn: button::State::new(),
         }
     }
 
-    pub fn view(&mut self, _selected: &usize) -> Element<RowMessage> {
+    fn probe_command(&self) -> Command<ListMessage> {
+        let id = self.id;
+        let hostname = self.server.hostname.clone();
+        let port = self.server.port;
+        Command::perform(
+            probe(hostname.clone(), port),
+            move |status| ListMessage::ProbeComplete(id, hostname.clone(), status)
+        )
+    }
+
+    pub fn view(&mut self, _selected: &usize) -> Element<'_, RowMessage> {
+        let ping = match self.probe.rtt() {
+            Some(rtt) => format!("{} ms", rtt.as_millis()),
+            None => "-".to_string(),
+        };
+        let status = match self.probe {
+            ProbeStatus::Pending => TEXT_LOCALIZED_STRING[&TextType::Probing],
+            ProbeStatus::Reachable(_) => TEXT_LOCALIZED_STRING[&TextType::Reachable],
+            ProbeStatus::Unreachable(_) | ProbeStatus::Unresolved(_) => TEXT_LOCALIZED_STRING[&TextType::Unreachable],
+        };
+
         Row::new()
             .push(
                 Button::new(
@@ -43,7 +70,9 @@ impl ServerRow {
                         .align_items(Alignment::Center)
                         .push(Text::new(&self.server.name).width(Length::FillPortion(1)))
                         .push(Text::new(&self.server.hostname).width(Length::FillPortion(1)))
-                        .push(Text::new(&self.server.player_count.to_string()).width(Length::FillPortion(1)))
+                        .push(Text::new(self.server.player_count.to_string()).width(Length::FillPortion(1)))
+                        .push(Text::new(ping).width(Length::FillPortion(1)))
+                        .push(Text::new(status).width(Length::FillPortion(1)))
                 )
                 .padding(8)
                 .width(Length::Fill)
@@ -55,22 +84,49 @@ impl ServerRow {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortColumn {
+    Name,
+    Address,
+    PlayerCount,
+    Ping,
+    Status,
+}
+
+const COLUMNS: [SortColumn; 5] = [
+    SortColumn::Name,
+    SortColumn::Address,
+    SortColumn::PlayerCount,
+    SortColumn::Ping,
+    SortColumn::Status,
+];
+
 pub struct ServerList {
     pub rows: Vec<ServerRow>,
+    // Id of the selected row, 0 when there's none
     pub selected: usize,
     pub manual_server_offset: usize,
+    // Set when the list came from the cache because no master could be reached
+    pub stale: Option<Duration>,
+    // Masters that failed and caches that couldn't be saved during the last refresh
+    pub problems: Vec<String>,
+    // Column and whether it's ascending
+    pub sort: Option<(SortColumn, bool)>,
 
     scrollable: scrollable::State,
+    head_btns: [button::State; 5],
 }
 
 #[derive(Debug, Clone)]
 pub enum ListMessage {
     //SearchInputChanged(String),
     UpdateServerList,
-    UpdateServerListComplete(Vec<Server>),
+    UpdateServerListComplete(ServerListing),
     ImportConfig(Vec<Server>),
     Fail(FailReason, String),
     RowMessage(usize, RowMessage),
+    ProbeComplete(usize, String, ProbeStatus),
+    SortBy(SortColumn),
 }
 
 impl ServerList {
@@ -86,13 +142,17 @@ impl ServerList {
                 ServerRow::new(server, id, false)
             }).collect(),
             selected: 0,
-            manual_server_offset: 0,
+            manual_server_offset: 1,
+            stale: None,
+            problems: Vec::new(),
+            sort: None,
 
             scrollable: scrollable::State::new(),
+            head_btns: Default::default(),
         }
     }
 
-    pub fn update(&mut self, message: ListMessage, api: &MasterServerApi) -> Command<ListMessage> {
+    pub fn update(&mut self, message: ListMessage, api: &MasterServerPool) -> Command<ListMessage> {
         match message {
             ListMessage::UpdateServerList => {
                 let api = api.clone();
@@ -102,18 +162,37 @@ impl ServerList {
                     },
                     move |res| {
                         match res {
-                            Ok(servers) => {
-                                ListMessage::UpdateServerListComplete(servers)
+                            Ok(listing) => {
+                                ListMessage::UpdateServerListComplete(listing)
                             },
                             Err(e) => {
-                                ListMessage::Fail(FailReason::RefreshListFail, e.to_string())
+                                ListMessage::Fail(FailReason::from_api_error(&e, FailReason::RefreshListFail), e.to_string())
                             }
                         }
                     }
                 );
             },
-            ListMessage::UpdateServerListComplete(servers) => {
-                self.rebuild_list(servers);
+            ListMessage::UpdateServerListComplete(listing) => {
+                self.stale = listing.stale;
+                self.problems = listing.problems;
+                self.rebuild_list(listing.servers);
+                self.apply_sort();
+                return self.probe_all();
+            },
+            ListMessage::ProbeComplete(id, hostname, status) => {
+                if let Some(row) = self.find_by_id_mut(id).filter(|row| row.server.hostname == hostname) {
+                    row.probe = status;
+                }
+                if matches!(self.sort, Some((SortColumn::Ping, _)) | Some((SortColumn::Status, _))) {
+                    self.apply_sort();
+                }
+            },
+            ListMessage::SortBy(column) => {
+                self.sort = match self.sort {
+                    Some((c, asc)) if c == column => Some((column, !asc)),
+                    _ => Some((column, true)),
+                };
+                self.apply_sort();
             },
             ListMessage::Fail(_, _) => {},
             ListMessage::RowMessage(id, row_message) => {
@@ -122,39 +201,64 @@ impl ServerList {
                         self.rows.retain(|row| row.id != id);
                     }
                     RowMessage::ToggleSelection => {
-                        match self.rows.iter_mut().filter(|row| row.id == id).next() {
-                            Some(_) => {
-                                self.selected = id
-                            }, 
-                            None => {}
+                        if self.rows.iter().any(|row| row.id == id) {
+                            self.selected = id
                         }
                     },
                 }
             }
             ListMessage::ImportConfig(servers) => {
                 self.import(servers);
+                self.apply_sort();
+                return self.probe_all();
             }
         }
         Command::none()
     }
 
-    pub fn view(&mut self, heads: [&str;3]) -> Element<ListMessage> {
-        let head = Row::with_children(heads.iter().map(|head| Text::new(*head).width(Length::FillPortion(1)).into()).collect());
+    /// Show the servers of the game `profile` is for.
+    pub fn view(&mut self, heads: [&str;5], profile: &GameProfile) -> Element<'_, ListMessage> {
+        let sort = self.sort;
+        let head = Row::with_children(
+            self.head_btns
+                .iter_mut()
+                .zip(heads.iter().zip(COLUMNS))
+                .map(|(state, (head, column))| {
+                    let label = match sort {
+                        Some((c, true)) if c == column => format!("{} ↑", head),
+                        Some((c, false)) if c == column => format!("{} ↓", head),
+                        _ => head.to_string(),
+                    };
+                    Button::new(state, Text::new(label))
+                        .width(Length::FillPortion(1))
+                        .on_press(ListMessage::SortBy(column))
+                        .into()
+                })
+                .collect()
+        )
+        .padding([0, 23, 0, 8]);
         let scrollable = Scrollable::new(&mut self.scrollable)
             .push(
                 Column::with_children(
-                    self.rows.iter_mut().map(
+                    self.rows.iter_mut().filter(|row| profile.lists(&row.server)).map(
                         |row| {
                             let id = row.id;
                             row.view(&self.selected).map(
                                 move |row_message| ListMessage::RowMessage(id, row_message)
-                            ).into()
+                            )
                         }
                     ).collect()
                 )
             );
 
-        Column::new()
+        let mut col = Column::new();
+        if let Some(age) = self.stale {
+            col = col.push(Text::new(format!("{} {}", TEXT_LOCALIZED_STRING[&TextType::StaleServerList], format_age(age))));
+        }
+        for problem in &self.problems {
+            col = col.push(Text::new(problem.as_str()));
+        }
+        col
             .push(head)
             .push(scrollable)
             .height(Length::Fill)
@@ -163,19 +267,59 @@ impl ServerList {
     }
 
     pub fn find_by_id(&self, id: usize) -> Option<&ServerRow> {
-        self.rows.iter().filter(|row| row.id == id).next()
+        self.rows.iter().find(|row| row.id == id)
     }
 
     pub fn find_by_id_mut(&mut self, id: usize) -> Option<&mut ServerRow> {
-        self.rows.iter_mut().filter(|row| row.id == id).next()
+        self.rows.iter_mut().find(|row| row.id == id)
+    }
+
+    /// Select the row of `hostname`, returns whether it's listed.
+    pub fn select_hostname(&mut self, hostname: &str) -> bool {
+        match self.rows.iter().find(|row| row.server.hostname.eq_ignore_ascii_case(hostname)) {
+            Some(row) => {
+                self.selected = row.id;
+                true
+            },
+            None => false,
+        }
     }
 
     pub fn find_selected_mut(&mut self) -> Option<&mut ServerRow> {
         self.find_by_id_mut(self.selected)
     }
 
-    fn rebuild_list(&mut self, servers: Vec<Server>) {
+    fn probe_all(&mut self) -> Command<ListMessage> {
+        Command::batch(
+            self.rows
+                .iter_mut()
+                .filter(|row| row.probe == ProbeStatus::Pending)
+                .map(|row| row.probe_command())
+                .collect::<Vec<_>>()
+        )
+    }
+
+    fn apply_sort(&mut self) {
+        let (column, asc) = match self.sort {
+            Some(sort) => sort,
+            None => return,
+        };
+        self.rows.sort_by(|a, b| {
+            let ord = match column {
+                SortColumn::Name => a.server.name.to_lowercase().cmp(&b.server.name.to_lowercase()),
+                SortColumn::Address => a.server.hostname.cmp(&b.server.hostname),
+                SortColumn::PlayerCount => a.server.player_count.cmp(&b.server.player_count),
+                SortColumn::Ping => a.probe.cmp(&b.probe),
+                SortColumn::Status => a.probe.rank().cmp(&b.probe.rank()),
+            };
+            if asc { ord } else { ord.reverse() }
+        });
+    }
+
+    fn rebuild_list(&mut self, mut servers: Vec<Server>) {
         self.rows.retain(|row| row.is_manual);
+        // Imported servers that are listed too keep their row, and the selection on it
+        servers.retain(|server| !self.rows.iter().any(|row| row.server.hostname.eq_ignore_ascii_case(&server.hostname)));
         self.rows
             .append(&mut
                 servers.into_iter()
@@ -202,4 +346,13 @@ impl ServerList {
                 })
             );
     }
-}
\ No newline at end of file
+}
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}min", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
@@ -1,8 +1,35 @@
-use iced::{button, Button, Command, Element, Length, Text, Row, Alignment};
+use std::fmt;
+
+use iced::{button, pick_list, Button, Checkbox, PickList, Command, Element, Length, Text, Row, Alignment};
+
+use crate::localize::{TEXT_LOCALIZED_STRING, TextType};
+use crate::patch::GameProcess;
+use crate::profile::Game;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstanceChoice {
+    All,
+    One(GameProcess),
+}
+
+impl fmt::Display for InstanceChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstanceChoice::All => write!(f, "{}", TEXT_LOCALIZED_STRING[&TextType::AllInstances]),
+            InstanceChoice::One(process) => write!(f, "{}", process),
+        }
+    }
+}
 
 pub struct TopBar {
     refresh_btn: button::State,
     import_btn: button::State,
+    restore_btn: button::State,
+    patch_exe_btn: button::State,
+    inspect_btn: button::State,
+    launch_btn: button::State,
+    game_pick: pick_list::State<Game>,
+    instance_pick: pick_list::State<InstanceChoice>,
 
     about_btn: button::State,
 }
@@ -11,6 +38,13 @@ pub struct TopBar {
 pub enum TopBarMessage {
     RefreshServerList,
     ChooseConfigFile,
+    Restore,
+    PatchExe,
+    Inspect,
+    Launch,
+    AutoPatch(bool),
+    ChooseGame(Game),
+    ChooseInstance(InstanceChoice),
     ShowAbout,
 }
 
@@ -19,6 +53,12 @@ impl TopBar {
         Self {
             refresh_btn: button::State::new(),
             import_btn: button::State::new(),
+            restore_btn: button::State::new(),
+            patch_exe_btn: button::State::new(),
+            inspect_btn: button::State::new(),
+            launch_btn: button::State::new(),
+            game_pick: pick_list::State::default(),
+            instance_pick: pick_list::State::default(),
             about_btn: button::State::new(),
         }
     }
@@ -34,7 +74,7 @@ impl TopBar {
         Command::none()
     }
 
-    pub fn view(&mut self) -> Element<TopBarMessage> {
+    pub fn view(&mut self, game: Game, auto_patch: bool, instances: &[GameProcess], chosen: Option<InstanceChoice>) -> Element<'_, TopBarMessage> {
         let refresh_btn = Button::new(
             &mut self.refresh_btn,
             Text::new("\u{E800}").font(crate::gui::ICON_FONT)
@@ -49,6 +89,35 @@ impl TopBar {
             .height(Length::Units(50))
             .width(Length::Units(50))
             .on_press(TopBarMessage::ChooseConfigFile);
+        let restore_btn = Button::new(
+            &mut self.restore_btn,
+            Text::new(TEXT_LOCALIZED_STRING[&TextType::Restore])
+        )
+            .height(Length::Units(50))
+            .on_press(TopBarMessage::Restore);
+        let patch_exe_btn = Button::new(
+            &mut self.patch_exe_btn,
+            Text::new(TEXT_LOCALIZED_STRING[&TextType::PatchExe])
+        )
+            .height(Length::Units(50))
+            .on_press(TopBarMessage::PatchExe);
+        let inspect_btn = Button::new(
+            &mut self.inspect_btn,
+            Text::new(TEXT_LOCALIZED_STRING[&TextType::Inspect])
+        )
+            .height(Length::Units(50))
+            .on_press(TopBarMessage::Inspect);
+        let launch_btn = Button::new(
+            &mut self.launch_btn,
+            Text::new(TEXT_LOCALIZED_STRING[&TextType::LaunchAndConnect])
+        )
+            .height(Length::Units(50))
+            .on_press(TopBarMessage::Launch);
+        let auto_patch = Checkbox::new(
+            auto_patch,
+            TEXT_LOCALIZED_STRING[&TextType::AutoPatch],
+            TopBarMessage::AutoPatch
+        );
         let about_btn = Button::new(
             &mut self.about_btn,
             Text::new("About")
@@ -57,9 +126,30 @@ impl TopBar {
             .width(Length::Units(50))
             .on_press(TopBarMessage::ShowAbout);
 
-        Row::new()
+        let game_pick = PickList::new(&mut self.game_pick, &Game::ALL[..], Some(game), TopBarMessage::ChooseGame);
+
+        let mut row = Row::new()
+            .push(game_pick)
             .push(refresh_btn)
             .push(import_btn)
+            .push(launch_btn)
+            .push(restore_btn)
+            .push(patch_exe_btn)
+            .push(inspect_btn)
+            .push(auto_patch);
+
+        // Only worth asking when there's a choice
+        if instances.len() > 1 {
+            let choices: Vec<InstanceChoice> = std::iter::once(InstanceChoice::All)
+                .chain(instances.iter().cloned().map(InstanceChoice::One))
+                .collect();
+            row = row.push(
+                PickList::new(&mut self.instance_pick, choices, chosen, TopBarMessage::ChooseInstance)
+                    .placeholder(TEXT_LOCALIZED_STRING[&TextType::ChooseInstance])
+            );
+        }
+
+        row
             .push(iced::Space::with_width(Length::Fill))
             .push(about_btn)
             .spacing(10)